@@ -1,37 +1,345 @@
-use crate::agents::model::{AgentInfo, AgentOutputBuffer, CreateAgentRequest, CreateAgentResponse};
+use crate::agents::model::{
+    AgentEvent, AgentEventKind, AgentInfo, AgentOutputBuffer, AgentSignal, AgentState, AgentStatus,
+    AgentSummary, CreateAgentRequest, CreateAgentResponse, ExitInfo, ExpectOutcome, ExpectStep,
+    ExpectStepResult, OutputFilter, OutputPage, OutputRecord, OutputStream, PtySize, ReapRecord,
+    ResourceLimits, RestartMode, RestartPolicy, StopOutcome, StopPath, WaitCondition, WaitOutcome,
+    WaitReason,
+};
+use crate::agents::process;
+use crate::agents::terminal;
+use crate::agents::transcript::{self, TranscriptConfig, TranscriptPage, TranscriptWriter};
 use crate::errors::AgentError;
 use dashmap::DashMap;
 use parking_lot::Mutex;
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::{HashMap, VecDeque};
 use std::path::PathBuf;
 use std::process::Stdio;
-use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
 use time::OffsetDateTime;
-use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
 use tokio::process::{Child, Command};
+use tokio::sync::{broadcast, mpsc, watch, Notify};
 // no direct tokio::time imports needed at module scope
 use uuid::Uuid;
 
+// How many reap records to keep for metrics/list_agents
+const MAX_REAP_RECORDS: usize = 100;
+// Default time a process gets to exit after SIGTERM before escalating to SIGKILL
+const DEFAULT_STOP_GRACE: Duration = Duration::from_secs(5);
+// How long to wait for the process to disappear after SIGKILL
+const KILL_WAIT: Duration = Duration::from_millis(1500);
+// Uptime after which a process counts as stable and the crash-loop counter resets
+const STABLE_UPTIME: Duration = Duration::from_secs(60);
+// Events buffered per subscriber before it starts lagging
+const EVENT_CAPACITY: usize = 1024;
+// After the process exits, how long output waits keep reading what the pumps still drain
+const EXIT_DRAIN: Duration = Duration::from_millis(100);
+// Most transcript records one read returns
+pub const MAX_TRANSCRIPT_PAGE: usize = 5000;
+// How long a transcript read waits for the writer to flush output recorded before it
+const TRANSCRIPT_CATCH_UP: Duration = Duration::from_secs(2);
+
 #[derive(Clone)]
 pub struct AgentManagerImpl {
     cursor_agent_path: Option<String>,
     buffer_bytes: usize,
+    idle_reap: Option<Duration>,
+    stop_grace: Duration,
+    transcripts: Option<TranscriptConfig>,
     agents: Arc<DashMap<String, Arc<AgentHandle>>>,
     metrics: Arc<AgentMetrics>,
+    reaped: Arc<Mutex<VecDeque<ReapRecord>>>,
+    events: broadcast::Sender<AgentEvent>,
 }
 
 pub struct AgentHandle {
     pub id: String,
     pub name: Option<String>,
     pub created_at: OffsetDateTime,
-    pub child: Mutex<Child>,
+    pub process: Mutex<AgentProcess>,
+    pub state: watch::Sender<AgentState>,
     pub buffer: Mutex<AgentOutputBuffer>,
     pub orig_args: Vec<String>,
     pub orig_env: HashMap<String, String>,
     pub orig_working_dir: Option<PathBuf>,
+    pub limits: ResourceLimits,
+    pub restart_policy: RestartPolicy,
     // Last time this agent produced output or received input
     pub last_used: Mutex<OffsetDateTime>,
+    pub idle_reap_exempt: bool,
+    // Keep the raw form of normalized lines in the buffer, not just in the transcript
+    pub keep_raw_output: bool,
+    // Full output on disk; the ring buffer only keeps the tail
+    pub transcript: Option<TranscriptSink>,
+    // Window size for agents running under a pty; respawns open their terminal at this size
+    pub pty_size: Option<Mutex<PtySize>>,
+    pub last_summary: Mutex<Option<AgentSummary>>,
+    // Sequence number after the newest recorded line; the pumps publish here so waiters wake on
+    // output instead of polling
+    output_seq: watch::Sender<u64>,
+    // Held for a whole send_and_expect script so nothing else writes to the agent meanwhile
+    interaction: tokio::sync::Mutex<()>,
+    events: broadcast::Sender<AgentEvent>,
+}
+
+/// The OS process currently behind an agent; replaced on hard reset. The child itself is owned
+/// by its waiter task.
+pub struct AgentProcess {
+    /// Also the process group id
+    pub pid: Option<u32>,
+    /// The stdin pipe, or the pty master for agents running under a terminal
+    pub stdin: Option<InputPipe>,
+    pty: Option<process::Pty>,
+    exit: watch::Receiver<Option<ExitInfo>>,
+    // Set at exit when other processes were still in the group. Only then may a stop after the
+    // exit sweep the group: once it empties, its id can be reused by an unrelated process.
+    group_outlived: Arc<AtomicBool>,
+    kill: Arc<Notify>,
+}
+
+/// Feeds an agent's transcript. Records are appended in batches on the blocking pool, so file IO
+/// never runs on the runtime threads or under the buffer lock.
+pub struct TranscriptSink {
+    pub path: PathBuf,
+    records: mpsc::UnboundedSender<OutputRecord>,
+    // Sequence number after the newest record on disk
+    written: watch::Receiver<u64>,
+}
+
+impl TranscriptSink {
+    fn start(mut writer: TranscriptWriter, agent_id: &str) -> Self {
+        let path = writer.path();
+        let (records, mut rx) = mpsc::unbounded_channel::<OutputRecord>();
+        let (written_tx, written) = watch::channel(0);
+        let agent_id = agent_id.to_string();
+        // Ends once the agent is dropped and everything queued is on disk
+        tokio::spawn(async move {
+            while let Some(rec) = rx.recv().await {
+                let mut batch = vec![rec];
+                while let Ok(rec) = rx.try_recv() {
+                    batch.push(rec);
+                }
+                let next = batch.last().map_or(0, |r| r.seq + 1);
+                let appended = tokio::task::spawn_blocking(move || {
+                    let res = writer.append(&batch);
+                    (writer, res)
+                })
+                .await;
+                let Ok((w, res)) = appended else {
+                    tracing::error!(
+                        agent_id,
+                        "transcript writer panicked; transcript stops here"
+                    );
+                    break;
+                };
+                writer = w;
+                if let Err(e) = res {
+                    tracing::warn!(agent_id, error=%e, "failed to write transcript");
+                }
+                // Published even after a failure so readers do not wait for records that never land
+                written_tx.send_replace(next);
+            }
+        });
+        Self {
+            path,
+            records,
+            written,
+        }
+    }
+
+    fn send(&self, rec: OutputRecord) {
+        let _ = self.records.send(rec);
+    }
+}
+
+type InputPipe = Box<dyn AsyncWrite + Send + Unpin>;
+type OutputPipe = Box<dyn AsyncRead + Send + Unpin>;
+
+// Parts of a freshly spawned child handed to the pump and waiter tasks
+struct ProcessIo {
+    child: Child,
+    stdout: Option<OutputPipe>,
+    stderr: Option<OutputPipe>,
+    exit_tx: watch::Sender<Option<ExitInfo>>,
+    group_outlived: Arc<AtomicBool>,
+    kill: Arc<Notify>,
+}
+
+// A pty agent reads and writes through its terminal, which merges stderr into stdout
+fn split_child(
+    mut child: Child,
+    pty: Option<process::Pty>,
+) -> Result<(AgentProcess, ProcessIo), AgentError> {
+    let (stdin, stdout, stderr): (Option<InputPipe>, Option<OutputPipe>, Option<OutputPipe>) =
+        match &pty {
+            Some(pty) => {
+                let open_err = |e: std::io::Error| AgentError::Io(format!("open pty: {e}"));
+                (
+                    Some(Box::new(pty.open().map_err(open_err)?)),
+                    Some(Box::new(pty.open().map_err(open_err)?)),
+                    None,
+                )
+            }
+            None => (
+                child.stdin.take().map(|p| Box::new(p) as InputPipe),
+                child.stdout.take().map(|p| Box::new(p) as OutputPipe),
+                child.stderr.take().map(|p| Box::new(p) as OutputPipe),
+            ),
+        };
+    let (exit_tx, exit) = watch::channel(None);
+    let kill = Arc::new(Notify::new());
+    let group_outlived = Arc::new(AtomicBool::new(false));
+    let process = AgentProcess {
+        pid: child.id(),
+        stdin,
+        pty,
+        exit,
+        group_outlived: group_outlived.clone(),
+        kill: kill.clone(),
+    };
+    let io = ProcessIo {
+        stdout,
+        stderr,
+        child,
+        exit_tx,
+        group_outlived,
+        kill,
+    };
+    Ok((process, io))
+}
+
+impl AgentHandle {
+    // Store a line from one of the pumps in the buffer and the transcript, rendered the way a
+    // terminal would show it
+    fn record_output(&self, stream: OutputStream, line: String) {
+        let (text, raw) = match terminal::normalize_line(&line) {
+            Cow::Borrowed(_) => (line, None),
+            Cow::Owned(text) => (text, Some(line)),
+        };
+        // The transcript always gets the raw form; the buffer only when the agent keeps it
+        let (kept, unkept) = if self.keep_raw_output {
+            (raw, None)
+        } else {
+            (None, raw)
+        };
+        let rec = {
+            let mut buffer = self.buffer.lock();
+            let rec = buffer.push_line(stream, text, kept);
+            // Queue under the buffer lock so records reach the transcript in sequence order
+            if let Some(transcript) = &self.transcript {
+                transcript.send(OutputRecord {
+                    raw: unkept.or_else(|| rec.raw.clone()),
+                    ..rec.clone()
+                });
+            }
+            rec
+        };
+        *self.last_used.lock() = rec.ts;
+        self.output_seq.send_replace(rec.seq + 1);
+        self.emit(AgentEventKind::Output);
+    }
+
+    fn clear_output(&self) {
+        self.buffer.lock().clear();
+        self.emit(AgentEventKind::Output);
+    }
+
+    fn emit(&self, kind: AgentEventKind) {
+        // No receivers just means nobody is listening
+        let _ = self.events.send(AgentEvent {
+            agent_id: self.id.clone(),
+            kind,
+        });
+    }
+
+    fn info(&self) -> AgentInfo {
+        let state = self.state.borrow().clone();
+        AgentInfo {
+            agent_id: self.id.clone(),
+            name: self.name.clone(),
+            pid: self.process.lock().pid.unwrap_or_default(),
+            created_at: self.created_at,
+            last_used: *self.last_used.lock(),
+            idle_reap_exempt: self.idle_reap_exempt,
+            limits: self.limits.clone(),
+            status: state.status.label().to_string(),
+            exit_code: state.last_exit.and_then(|x| x.code),
+            exit_signal: state.last_exit.and_then(|x| x.signal),
+            exited_at: state.last_exit.map(|x| x.at),
+            restart_policy: self.restart_policy.clone(),
+            restart_count: state.restart_count,
+            crash_loop: state.crash_loop,
+            transcript_path: self.transcript.as_ref().map(|t| t.path.clone()),
+            pty: self.pty_size.as_ref().map(|s| *s.lock()),
+        }
+    }
+
+    fn set_status(&self, status: AgentStatus) {
+        self.state.send_modify(|s| s.status = status);
+        self.emit(AgentEventKind::Status);
+    }
+
+    // Record the exit of process `generation`; ignored if a newer process has since been spawned
+    fn record_exit(&self, generation: u64, info: ExitInfo) {
+        let changed = self.state.send_if_modified(|s| {
+            if s.generation != generation {
+                return false;
+            }
+            s.last_exit = Some(info);
+            // Intentional stops/restarts keep their status; only an unexpected exit changes it
+            if matches!(
+                s.status,
+                AgentStatus::Starting | AgentStatus::Running | AgentStatus::Paused
+            ) {
+                s.status = info.status();
+            }
+            true
+        });
+        if changed {
+            self.emit(AgentEventKind::Status);
+        }
+    }
+
+    // After an unexpected exit of process `generation`, decide whether the restart policy wants
+    // it respawned. Returns the backoff to wait and marks the agent restarting, or flags a crash loop.
+    fn plan_restart(&self, generation: u64, info: ExitInfo) -> Option<Duration> {
+        let policy = &self.restart_policy;
+        let mut delay = None;
+        let changed = self.state.send_if_modified(|s| {
+            if s.generation != generation {
+                return false;
+            }
+            let wanted = match (policy.mode, s.status) {
+                (RestartMode::Never, _) => false,
+                (RestartMode::OnFailure, AgentStatus::Exited(code)) => code != 0,
+                (RestartMode::OnFailure, AgentStatus::Signaled(_)) => true,
+                (RestartMode::Always, AgentStatus::Exited(_) | AgentStatus::Signaled(_)) => true,
+                _ => false,
+            };
+            if !wanted {
+                return false;
+            }
+            if info.at - s.started_at >= STABLE_UPTIME {
+                s.consecutive_restarts = 0;
+            }
+            if s.consecutive_restarts >= policy.max_retries {
+                s.crash_loop = true;
+                return true;
+            }
+            delay = Some(policy.backoff(s.consecutive_restarts));
+            s.consecutive_restarts += 1;
+            s.restart_count += 1;
+            s.status = AgentStatus::Restarting;
+            true
+        });
+        if changed {
+            self.emit(AgentEventKind::Status);
+        }
+        delay
+    }
 }
 
 impl AgentManagerImpl {
@@ -39,11 +347,34 @@ impl AgentManagerImpl {
         Self {
             cursor_agent_path,
             buffer_bytes,
+            idle_reap: None,
+            stop_grace: DEFAULT_STOP_GRACE,
+            transcripts: None,
             agents: Arc::new(DashMap::new()),
             metrics: Arc::new(AgentMetrics::default()),
+            reaped: Arc::new(Mutex::new(VecDeque::new())),
+            events: broadcast::channel(EVENT_CAPACITY).0,
         }
     }
 
+    /// Stop agents that have been idle (no input or output) for longer than `window`.
+    pub fn with_idle_reap(mut self, window: Option<Duration>) -> Self {
+        self.idle_reap = window;
+        self
+    }
+
+    /// Default time agents get to exit after SIGTERM before `stop` escalates to SIGKILL.
+    pub fn with_stop_grace(mut self, grace: Duration) -> Self {
+        self.stop_grace = grace;
+        self
+    }
+
+    /// Where and how agent transcripts are written. `None` disables transcripts.
+    pub fn with_transcripts(mut self, config: Option<TranscriptConfig>) -> Self {
+        self.transcripts = config;
+        self
+    }
+
     fn resolve_binary(&self) -> Result<String, AgentError> {
         if let Some(p) = &self.cursor_agent_path {
             return Ok(p.clone());
@@ -56,47 +387,70 @@ impl AgentManagerImpl {
     pub async fn create(&self, req: CreateAgentRequest) -> Result<CreateAgentResponse, AgentError> {
         let id = Uuid::new_v4().to_string();
         let bin = self.resolve_binary()?;
+        let transcript = match (req.transcript, &self.transcripts) {
+            (Some(false), _) | (None, None) => None,
+            (_, Some(config)) => Some(TranscriptWriter::create(config.clone(), &id)?),
+            (Some(true), None) => {
+                return Err(AgentError::InvalidState(
+                    "transcripts are disabled; set TRANSCRIPT_DIR to enable them".into(),
+                ))
+            }
+        };
 
-        let mut cmd = Command::new(&bin);
-        for a in &req.args {
-            cmd.arg(a);
-        }
-
-        if let Some(dir) = &req.working_dir {
-            cmd.current_dir(dir);
-        }
-
-        // Allowlist env pass-through: only explicit provided entries
-        if !req.env.is_empty() {
-            cmd.envs(&req.env);
+        if req.pty.is_some_and(|s| s.rows == 0 || s.cols == 0) {
+            return Err(AgentError::InvalidState(
+                "pty rows and cols must be positive".into(),
+            ));
         }
-
-        let child = cmd
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .spawn()
-            .map_err(|e| AgentError::Spawn(format!("Failed to spawn cursor-agent: {}. Ensure cursor-agent is installed and on PATH or set CURSOR_AGENT_PATH.", e)))?;
-
-        let pid = child.id().unwrap_or_default();
+        let (child, pty) = spawn_child(
+            &bin,
+            &req.args,
+            req.working_dir.as_ref(),
+            &req.env,
+            &req.limits,
+            req.pty,
+        )?;
+        let (process, io) = split_child(child, pty)?;
+        let pid = process.pid.unwrap_or_default();
 
         let handle = Arc::new(AgentHandle {
             id: id.clone(),
             name: req.name.clone(),
             created_at: OffsetDateTime::now_utc(),
-            child: Mutex::new(child),
+            process: Mutex::new(process),
+            state: watch::channel(AgentState {
+                status: AgentStatus::Starting,
+                generation: 0,
+                started_at: OffsetDateTime::now_utc(),
+                last_exit: None,
+                restart_count: 0,
+                consecutive_restarts: 0,
+                crash_loop: false,
+            })
+            .0,
             buffer: Mutex::new(AgentOutputBuffer::new(self.buffer_bytes)),
             orig_args: req.args.clone(),
             orig_env: req.env.clone(),
             orig_working_dir: req.working_dir.clone(),
+            limits: req.limits.clone(),
+            restart_policy: req.restart_policy.clone(),
             last_used: Mutex::new(OffsetDateTime::now_utc()),
+            idle_reap_exempt: req.idle_reap_exempt,
+            keep_raw_output: req.keep_raw_output,
+            transcript: transcript.map(|w| TranscriptSink::start(w, &id)),
+            pty_size: req.pty.map(Mutex::new),
+            last_summary: Mutex::new(None),
+            output_seq: watch::channel(0).0,
+            interaction: tokio::sync::Mutex::new(()),
+            events: self.events.clone(),
         });
 
-        // Start stdout/stderr pumps
-        self.spawn_pumps(handle.clone());
+        // Start stdout/stderr pumps and the exit waiter
+        self.launch(&handle, io);
 
-        self.agents.insert(id.clone(), handle);
+        self.agents.insert(id.clone(), handle.clone());
         self.metrics.created_count.fetch_add(1, Ordering::Relaxed);
+        handle.emit(AgentEventKind::Created);
         Ok(CreateAgentResponse { agent_id: id, pid })
     }
 
@@ -104,213 +458,907 @@ impl AgentManagerImpl {
         let Some(handle) = self.agents.get(agent_id).map(|e| e.clone()) else {
             return Err(AgentError::NotFound(agent_id.to_string()));
         };
+        // Wait for a running send_and_expect script rather than interleaving with it
+        let _turn = handle.interaction.lock().await;
+        self.write_input(&handle, format!("{input}\n").as_bytes())
+            .await
+    }
+
+    /// Write `bytes` to the agent's stdin exactly as given, without adding a newline.
+    pub async fn send_bytes(&self, agent_id: &str, bytes: &[u8]) -> Result<(), AgentError> {
+        let Some(handle) = self.agents.get(agent_id).map(|e| e.clone()) else {
+            return Err(AgentError::NotFound(agent_id.to_string()));
+        };
+        let _turn = handle.interaction.lock().await;
+        self.write_input(&handle, bytes).await
+    }
+
+    /// Close the agent's stdin so it sees end-of-input. Returns false if it was already closed. A
+    /// hard reset or restart gives the new process a fresh stdin.
+    pub async fn close_stdin(&self, agent_id: &str) -> Result<bool, AgentError> {
+        let Some(handle) = self.agents.get(agent_id).map(|e| e.clone()) else {
+            return Err(AgentError::NotFound(agent_id.to_string()));
+        };
+        let _turn = handle.interaction.lock().await;
+        // Dropping the pipe closes it
+        let stdin = handle.process.lock().stdin.take();
+        let Some(mut stdin) = stdin else {
+            return Ok(false);
+        };
+        // A terminal stays open for output; the agent reads end-of-input from the EOF character
+        if handle.pty_size.is_some() {
+            stdin
+                .write_all(b"\x04")
+                .await
+                .map_err(|e| AgentError::Io(e.to_string()))?;
+            stdin
+                .flush()
+                .await
+                .map_err(|e| AgentError::Io(e.to_string()))?;
+        }
+        Ok(true)
+    }
+
+    /// Change the window size of an agent running under a pseudo-terminal. The agent gets
+    /// SIGWINCH, and later restarts keep the new size.
+    pub async fn resize_pty(&self, agent_id: &str, size: PtySize) -> Result<AgentInfo, AgentError> {
+        let Some(handle) = self.agents.get(agent_id).map(|e| e.clone()) else {
+            return Err(AgentError::NotFound(agent_id.to_string()));
+        };
+        let Some(current) = &handle.pty_size else {
+            return Err(AgentError::InvalidState(
+                "agent is not running under a pty; create it with `pty: true`".into(),
+            ));
+        };
+        if size.rows == 0 || size.cols == 0 {
+            return Err(AgentError::InvalidState(
+                "pty rows and cols must be positive".into(),
+            ));
+        }
+        *current.lock() = size;
+        if let Some(pty) = &handle.process.lock().pty {
+            pty.resize(size)
+                .map_err(|e| AgentError::Io(format!("resize pty: {e}")))?;
+        }
+        Ok(handle.info())
+    }
+
+    /// Send `signal` to the agent's process group. SIGSTOP marks the agent paused until SIGCONT.
+    pub async fn signal(
+        &self,
+        agent_id: &str,
+        signal: AgentSignal,
+    ) -> Result<AgentInfo, AgentError> {
+        let Some(handle) = self.agents.get(agent_id).map(|e| e.clone()) else {
+            return Err(AgentError::NotFound(agent_id.to_string()));
+        };
+        let status = handle.state.borrow().status;
+        let live = matches!(
+            status,
+            AgentStatus::Starting | AgentStatus::Running | AgentStatus::Paused
+        );
+        let Some(pgid) = handle.process.lock().pid.filter(|_| live) else {
+            return Err(AgentError::InvalidState(format!(
+                "agent is {}; there is no process to signal",
+                status.label()
+            )));
+        };
+        process::send_signal(pgid, signal).map_err(|e| match e.kind() {
+            std::io::ErrorKind::Unsupported => AgentError::InvalidState(e.to_string()),
+            _ => AgentError::Io(format!("send {}: {e}", signal.name())),
+        })?;
+        // Only move between running and paused; an exit recorded meanwhile wins
+        let changed = handle.state.send_if_modified(|s| match (signal, s.status) {
+            (AgentSignal::Stop, AgentStatus::Starting | AgentStatus::Running) => {
+                s.status = AgentStatus::Paused;
+                true
+            }
+            (AgentSignal::Cont, AgentStatus::Paused) => {
+                s.status = AgentStatus::Running;
+                true
+            }
+            _ => false,
+        });
+        if changed {
+            handle.emit(AgentEventKind::Status);
+        }
+        tracing::info!(agent_id, signal = signal.name(), "signalled agent");
+        Ok(handle.info())
+    }
+
+    // Write to the agent's stdin; callers hold its interaction lock
+    async fn write_input(&self, handle: &AgentHandle, input: &[u8]) -> Result<(), AgentError> {
         // Avoid holding the lock across await: temporarily take stdin
+        let generation = handle.state.borrow().generation;
         let mut stdin_pipe = {
-            let mut child = handle.child.lock();
-            child
+            let mut process = handle.process.lock();
+            process
                 .stdin
                 .take()
                 .ok_or_else(|| AgentError::InvalidState("stdin not available".into()))?
         };
-        use tokio::io::AsyncWriteExt;
-        stdin_pipe
-            .write_all(input.as_bytes())
-            .await
-            .map_err(|e| AgentError::Io(e.to_string()))?;
         stdin_pipe
-            .write_all(b"\n")
+            .write_all(input)
             .await
             .map_err(|e| AgentError::Io(e.to_string()))?;
         stdin_pipe
             .flush()
             .await
             .map_err(|e| AgentError::Io(e.to_string()))?;
-        // Return stdin to child
+        // Return stdin to the process, unless it was replaced by a hard reset meanwhile
         {
-            let mut child = handle.child.lock();
-            child.stdin.replace(stdin_pipe);
+            let mut process = handle.process.lock();
+            if handle.state.borrow().generation == generation {
+                process.stdin.replace(stdin_pipe);
+            }
         }
         *handle.last_used.lock() = OffsetDateTime::now_utc();
         self.metrics
             .total_input_bytes
-            .fetch_add(input.len() as u64 + 1, Ordering::Relaxed);
+            .fetch_add(input.len() as u64, Ordering::Relaxed);
         Ok(())
     }
 
-    pub async fn reset(&self, agent_id: &str, hard: bool) -> Result<(), AgentError> {
-        let Some(entry) = self.agents.get(agent_id) else {
+    /// Soft reset clears the buffer. Hard reset kills the process tree and respawns it under the
+    /// same ID, returning how the old process went down.
+    pub async fn reset(
+        &self,
+        agent_id: &str,
+        hard: bool,
+    ) -> Result<Option<StopOutcome>, AgentError> {
+        // Clone the handle so no map guard is held across awaits (the reaper may remove concurrently)
+        let Some(entry) = self.agents.get(agent_id).map(|e| e.clone()) else {
             return Err(AgentError::NotFound(agent_id.to_string()));
         };
         if !hard {
-            entry.buffer.lock().lines.clear();
-            entry.buffer.lock().current_bytes = 0;
-            return Ok(());
+            entry.clear_output();
+            return Ok(None);
         }
-        // Hard reset: kill child and respawn with same config under same ID
+        // Hard reset: kill child and respawn with same config under same ID.
+        // A manual restart also gives the restart policy a fresh budget.
+        entry.state.send_modify(|s| {
+            s.consecutive_restarts = 0;
+            s.crash_loop = false;
+        });
+        self.respawn(&entry, true).await.map(Some)
+    }
+
+    // Kill the agent's current process tree and start a new one with the original config.
+    // Shared by hard reset and the restart policy.
+    async fn respawn(
+        &self,
+        entry: &Arc<AgentHandle>,
+        clear_buffer: bool,
+    ) -> Result<StopOutcome, AgentError> {
         let bin = self.resolve_binary()?;
-        // Best-effort terminate without holding the lock across awaits
-        {
-            let mut child = entry.child.lock();
-            let _ = child.start_kill();
-        }
-        let deadline = std::time::Instant::now() + std::time::Duration::from_millis(1500);
-        while std::time::Instant::now() < deadline {
-            let exited = {
-                let mut child = entry.child.lock();
-                child.try_wait().ok().flatten().is_some()
-            };
-            if exited {
-                break;
+        entry.set_status(AgentStatus::Restarting);
+        let outcome = terminate(entry, StopSignal::Kill, Duration::ZERO).await;
+        entry.set_status(AgentStatus::Starting);
+        let spawned = spawn_child(
+            &bin,
+            &entry.orig_args,
+            entry.orig_working_dir.as_ref(),
+            &entry.orig_env,
+            &entry.limits,
+            entry.pty_size.as_ref().map(|s| *s.lock()),
+        )
+        .and_then(|(child, pty)| split_child(child, pty));
+        let (process, io) = match spawned {
+            Ok(p) => p,
+            Err(e) => {
+                entry.set_status(AgentStatus::Stopped);
+                return Err(e);
             }
-            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
-        }
-        let mut cmd = Command::new(&bin);
-        for a in &entry.orig_args {
-            cmd.arg(a);
-        }
-        if let Some(dir) = &entry.orig_working_dir {
-            cmd.current_dir(dir);
+        };
+        // swap process and clear buffer
+        *entry.process.lock() = process;
+        if clear_buffer {
+            entry.clear_output();
         }
-        if !entry.orig_env.is_empty() {
-            cmd.envs(&entry.orig_env);
+        // restart pumps and waiter
+        self.launch(entry, io);
+        Ok(outcome)
+    }
+
+    // Apply the agent's restart policy after its process `generation` exited on its own
+    async fn maybe_restart(&self, handle: Arc<AgentHandle>, generation: u64, info: ExitInfo) {
+        let Some(delay) = handle.plan_restart(generation, info) else {
+            if handle.state.borrow().crash_loop {
+                tracing::error!(agent_id=%handle.id, max_retries=handle.restart_policy.max_retries, "agent is crash-looping; giving up on restarts");
+            }
+            return;
+        };
+        tracing::warn!(agent_id=%handle.id, delay_ms=delay.as_millis() as u64, "agent exited unexpectedly; restarting");
+        tokio::time::sleep(delay).await;
+        // Abort if the agent was stopped, reset or removed while backing off
+        let still_pending = {
+            let s = handle.state.borrow();
+            s.generation == generation && s.status == AgentStatus::Restarting
+        };
+        if !still_pending || !self.agents.contains_key(&handle.id) {
+            return;
         }
-        let new_child = cmd
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .spawn()
-            .map_err(|e| AgentError::Spawn(e.to_string()))?;
-        // swap child and clear buffer
-        {
-            let mut child = entry.child.lock();
-            *child = new_child;
+        if let Err(e) = self.respawn(&handle, false).await {
+            tracing::error!(agent_id=%handle.id, error=%e, "automatic restart failed");
         }
-        entry.buffer.lock().lines.clear();
-        entry.buffer.lock().current_bytes = 0;
-        // restart pumps
-        self.spawn_pumps(entry.clone());
-        Ok(())
     }
 
-    pub async fn stop(&self, agent_id: &str, signal: StopSignal) -> Result<(), AgentError> {
+    /// Stop and remove an agent. `Term` sends SIGTERM and escalates to SIGKILL once `grace`
+    /// (or the configured default) elapses; `Kill` sends SIGKILL right away.
+    pub async fn stop(
+        &self,
+        agent_id: &str,
+        signal: StopSignal,
+        grace: Option<Duration>,
+    ) -> Result<StopOutcome, AgentError> {
         let Some((_, handle)) = self.agents.remove(agent_id) else {
             return Err(AgentError::NotFound(agent_id.to_string()));
         };
-        match signal {
-            StopSignal::Term | StopSignal::Kill => {
-                {
-                    let mut child = handle.child.lock();
-                    let _ = child.start_kill();
-                }
-                // Poll until the process exits or timeout, without holding the lock across await
-                let deadline = std::time::Instant::now() + std::time::Duration::from_millis(1500);
-                while std::time::Instant::now() < deadline {
-                    let exited = {
-                        let mut child = handle.child.lock();
-                        child.try_wait().ok().flatten().is_some()
-                    };
-                    if exited {
-                        break;
-                    }
-                    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
-                }
-            }
-        }
+        handle.set_status(AgentStatus::Stopped);
+        handle.emit(AgentEventKind::Removed);
+        let outcome = terminate(&handle, signal, grace.unwrap_or(self.stop_grace)).await;
+        tracing::info!(agent_id, path=?outcome.path, exit_code=?outcome.exit_code, exit_signal=?outcome.exit_signal, stragglers=?outcome.stragglers, "agent stopped");
         self.metrics.stopped_count.fetch_add(1, Ordering::Relaxed);
-        Ok(())
+        Ok(outcome)
     }
 
     pub async fn list(&self) -> Vec<AgentInfo> {
-        self.agents
-            .iter()
-            .map(|e| AgentInfo {
-                agent_id: e.id.clone(),
-                name: e.name.clone(),
-                pid: e.child.lock().id().unwrap_or_default(),
-                created_at: e.created_at,
-                status: "running".to_string(),
-            })
-            .collect()
+        self.agents.iter().map(|e| e.info()).collect()
+    }
+
+    pub async fn info(&self, agent_id: &str) -> Result<AgentInfo, AgentError> {
+        let Some(handle) = self.agents.get(agent_id).map(|e| e.clone()) else {
+            return Err(AgentError::NotFound(agent_id.to_string()));
+        };
+        Ok(handle.info())
+    }
+
+    /// Remember the latest summary of an agent's output so it can be served as a resource.
+    pub async fn record_summary(
+        &self,
+        agent_id: &str,
+        summary: AgentSummary,
+    ) -> Result<(), AgentError> {
+        let Some(handle) = self.agents.get(agent_id).map(|e| e.clone()) else {
+            return Err(AgentError::NotFound(agent_id.to_string()));
+        };
+        *handle.last_summary.lock() = Some(summary);
+        handle.emit(AgentEventKind::Summary);
+        Ok(())
+    }
+
+    pub async fn latest_summary(&self, agent_id: &str) -> Result<Option<AgentSummary>, AgentError> {
+        let Some(handle) = self.agents.get(agent_id).map(|e| e.clone()) else {
+            return Err(AgentError::NotFound(agent_id.to_string()));
+        };
+        let summary = handle.last_summary.lock().clone();
+        Ok(summary)
     }
 
-    pub async fn get_buffer(&self, agent_id: &str) -> Result<String, AgentError> {
+    /// Buffered output matching `filter` rendered as text, e.g. for summarization.
+    pub async fn get_buffer(
+        &self,
+        agent_id: &str,
+        filter: &OutputFilter,
+    ) -> Result<String, AgentError> {
         let Some(handle) = self.agents.get(agent_id).map(|e| e.clone()) else {
             return Err(AgentError::NotFound(agent_id.to_string()));
         };
         let concatenated = {
             let lock = handle.buffer.lock();
-            lock.concat()
+            lock.concat(filter)
         };
         Ok(concatenated)
     }
 
-    fn spawn_pumps(&self, handle: Arc<AgentHandle>) {
-        // stdout
-        let mut child_for_out = handle.child.lock();
-        let stdout = child_for_out.stdout.take();
-        let stderr = child_for_out.stderr.take();
-        drop(child_for_out);
+    /// Read up to `limit` (at most MAX_TRANSCRIPT_PAGE) transcript records `from..` matching
+    /// `filter` back from disk, including everything the agent printed before the call. Works for
+    /// stopped agents as long as their files are still in the transcript directory.
+    pub async fn read_transcript(
+        &self,
+        agent_id: &str,
+        from: u64,
+        limit: usize,
+        filter: &OutputFilter,
+    ) -> Result<TranscriptPage, AgentError> {
+        let Some(config) = &self.transcripts else {
+            return Err(AgentError::InvalidState(
+                "transcripts are disabled; set TRANSCRIPT_DIR to enable them".into(),
+            ));
+        };
+        // Agent ids are UUIDs; reject anything else so it cannot escape the transcript dir
+        if Uuid::parse_str(agent_id).is_err() {
+            return Err(AgentError::NotFound(agent_id.to_string()));
+        }
+        // Let the writer catch up with output recorded so far
+        let handle = self.agents.get(agent_id).map(|e| e.clone());
+        if let Some(handle) = handle {
+            if let Some(sink) = &handle.transcript {
+                let target = *handle.output_seq.borrow();
+                let mut written = sink.written.clone();
+                let _ =
+                    tokio::time::timeout(TRANSCRIPT_CATCH_UP, written.wait_for(|w| *w >= target))
+                        .await;
+            }
+        }
+        let config = config.clone();
+        let agent_id = agent_id.to_string();
+        let filter = filter.clone();
+        let limit = limit.min(MAX_TRANSCRIPT_PAGE);
+        tokio::task::spawn_blocking(move || {
+            transcript::read_range(&config, &agent_id, from, limit, &filter)
+        })
+        .await
+        .map_err(|e| AgentError::Io(format!("read transcript: {e}")))?
+    }
+
+    /// Raw buffered records with sequence number >= `cursor` matching `filter`, for cheap
+    /// incremental tailing.
+    pub async fn read_output(
+        &self,
+        agent_id: &str,
+        cursor: u64,
+        limit: usize,
+        filter: &OutputFilter,
+    ) -> Result<OutputPage, AgentError> {
+        let Some(handle) = self.agents.get(agent_id).map(|e| e.clone()) else {
+            return Err(AgentError::NotFound(agent_id.to_string()));
+        };
+        let page = handle.buffer.lock().read_from(cursor, limit, filter);
+        Ok(page)
+    }
 
-        if let Some(stdout) = stdout {
-            let handle_out = handle.clone();
-            let metrics = self.metrics.clone();
-            tokio::spawn(async move {
-                let reader = BufReader::new(stdout);
-                let mut lines = reader.lines();
-                while let Ok(Some(line)) = lines.next_line().await {
-                    let len = line.len();
-                    handle_out.buffer.lock().push_line(line);
-                    *handle_out.last_used.lock() = OffsetDateTime::now_utc();
-                    metrics
-                        .total_output_bytes
-                        .fetch_add(len as u64 + 1, Ordering::Relaxed);
+    /// Wait until any part of `cond` holds for the agent, or `timeout` elapses. Lines are examined
+    /// from `cursor`, by default only those written after the call. Wakes on output from the pumps
+    /// and status changes from the waiter rather than polling.
+    pub async fn wait_for_output(
+        &self,
+        agent_id: &str,
+        cursor: Option<u64>,
+        cond: &WaitCondition,
+        timeout: Duration,
+    ) -> Result<WaitOutcome, AgentError> {
+        let Some(handle) = self.agents.get(agent_id).map(|e| e.clone()) else {
+            return Err(AgentError::NotFound(agent_id.to_string()));
+        };
+        let started = tokio::time::Instant::now();
+        let mut cursor = cursor.unwrap_or_else(|| *handle.output_seq.borrow());
+        let watch = OutputWatch {
+            filter: &cond.filter,
+            idle: cond.idle,
+            until_exit: cond.exit,
+            deadline: started + timeout,
+        };
+        let result = watch
+            .run(&handle, &mut cursor, |records, _| {
+                let pattern = cond.pattern.as_ref()?;
+                let matches: Vec<_> = records
+                    .into_iter()
+                    .filter(|r| pattern.is_match(&r.text))
+                    .collect();
+                (!matches.is_empty()).then_some(matches)
+            })
+            .await;
+        let (reason, matches) = match result {
+            Ok(matches) => (WaitReason::Matched, matches),
+            Err(reason) => (reason, Vec::new()),
+        };
+        let status = handle.state.borrow().status.label().to_string();
+        Ok(WaitOutcome {
+            reason,
+            matches,
+            next_cursor: cursor,
+            status,
+            waited_ms: started.elapsed().as_millis() as u64,
+        })
+    }
+
+    /// Run `steps` against the agent: each writes its input, if any, then waits for the first line
+    /// matching one of its patterns. Stops at the first step that times out or sees the process
+    /// exit. No other script or input reaches the agent until the whole script is done.
+    pub async fn send_and_expect(
+        &self,
+        agent_id: &str,
+        steps: &[ExpectStep],
+    ) -> Result<ExpectOutcome, AgentError> {
+        let Some(handle) = self.agents.get(agent_id).map(|e| e.clone()) else {
+            return Err(AgentError::NotFound(agent_id.to_string()));
+        };
+        let _turn = handle.interaction.lock().await;
+        let all = OutputFilter::default();
+        // Output from before the script is not part of its first step
+        let mut cursor = *handle.output_seq.borrow();
+        let mut results = Vec::with_capacity(steps.len());
+        for step in steps {
+            let started = tokio::time::Instant::now();
+            if let Some(input) = &step.send {
+                // Steps that already ran stay reported; the failed one ends the script
+                let sent = self
+                    .write_input(&handle, format!("{input}\n").as_bytes())
+                    .await;
+                if let Err(e) = sent {
+                    results.push(ExpectStepResult {
+                        reason: WaitReason::SendFailed,
+                        matched: None,
+                        pattern: None,
+                        output: Vec::new(),
+                        waited_ms: started.elapsed().as_millis() as u64,
+                        error: Some(e.to_string()),
+                    });
+                    break;
                 }
+            }
+            let watch = OutputWatch {
+                filter: &all,
+                idle: None,
+                until_exit: true,
+                deadline: started + step.timeout,
+            };
+            let mut output = Vec::new();
+            // Stop at the matching line; anything after it belongs to the next step
+            let result = watch
+                .run(&handle, &mut cursor, |records, cursor| {
+                    for rec in records {
+                        let hit = step.expect.iter().position(|p| p.is_match(&rec.text));
+                        *cursor = rec.seq + 1;
+                        output.push(rec);
+                        if hit.is_some() {
+                            return hit;
+                        }
+                    }
+                    None
+                })
+                .await;
+            let (reason, matched) = match result {
+                Ok(i) => (WaitReason::Matched, Some(i)),
+                Err(reason) => (reason, None),
+            };
+            results.push(ExpectStepResult {
+                reason,
+                matched,
+                pattern: matched.map(|i| step.expect[i].as_str().to_string()),
+                output,
+                waited_ms: started.elapsed().as_millis() as u64,
+                error: None,
             });
+            if matched.is_none() {
+                break;
+            }
         }
-        if let Some(stderr) = stderr {
-            let handle_err = handle.clone();
-            let metrics = self.metrics.clone();
-            tokio::spawn(async move {
-                let reader = BufReader::new(stderr);
-                let mut lines = reader.lines();
-                while let Ok(Some(line)) = lines.next_line().await {
-                    handle_err
-                        .buffer
-                        .lock()
-                        .push_line(format!("[stderr] {line}"));
-                    *handle_err.last_used.lock() = OffsetDateTime::now_utc();
-                    metrics
-                        .total_output_bytes
-                        .fetch_add(line.len() as u64 + 1, Ordering::Relaxed);
+        let status = handle.state.borrow().status.label().to_string();
+        Ok(ExpectOutcome {
+            completed: results.len() == steps.len()
+                && results.iter().all(|r| r.reason == WaitReason::Matched),
+            steps: results,
+            next_cursor: cursor,
+            status,
+        })
+    }
+
+    // Mark a new process generation running and start its pumps and waiter
+    fn launch(&self, handle: &Arc<AgentHandle>, io: ProcessIo) {
+        let mut generation = 0;
+        handle.state.send_modify(|s| {
+            s.generation += 1;
+            s.status = AgentStatus::Running;
+            s.started_at = OffsetDateTime::now_utc();
+            generation = s.generation;
+        });
+        handle.emit(AgentEventKind::Status);
+        self.spawn_pumps(handle.clone(), io.stdout, io.stderr);
+        self.spawn_waiter(
+            Arc::downgrade(handle),
+            generation,
+            io.child,
+            io.exit_tx,
+            io.group_outlived,
+            io.kill,
+        );
+    }
+
+    // Own the child until it exits, then publish its exit to the agent state and to `terminate`,
+    // and apply the restart policy. Holds only a weak handle so dropping the agent still runs its
+    // Drop cleanup.
+    fn spawn_waiter(
+        &self,
+        handle: Weak<AgentHandle>,
+        generation: u64,
+        mut child: Child,
+        exit_tx: watch::Sender<Option<ExitInfo>>,
+        group_outlived: Arc<AtomicBool>,
+        kill: Arc<Notify>,
+    ) {
+        let this = self.clone();
+        tokio::spawn(async move {
+            let pgid = child.id();
+            let status = loop {
+                tokio::select! {
+                    res = child.wait() => break res,
+                    _ = kill.notified() => {
+                        let _ = child.start_kill();
+                    }
                 }
+            };
+            let (code, signal) = status
+                .as_ref()
+                .map(process::exit_details)
+                .unwrap_or((None, None));
+            let info = ExitInfo {
+                code,
+                signal,
+                at: OffsetDateTime::now_utc(),
+            };
+            if let Some(pgid) = pgid {
+                let outlived = !process::group_members(pgid).is_empty();
+                group_outlived.store(outlived, Ordering::SeqCst);
+            }
+            let handle = handle.upgrade();
+            if let Some(handle) = &handle {
+                tracing::info!(agent_id=%handle.id, exit_code=?code, exit_signal=?signal, "agent process exited");
+                handle.record_exit(generation, info);
+            }
+            exit_tx.send_replace(Some(info));
+            if let Some(handle) = handle {
+                this.maybe_restart(handle, generation, info).await;
+            }
+        });
+    }
+
+    fn spawn_pumps(
+        &self,
+        handle: Arc<AgentHandle>,
+        stdout: Option<OutputPipe>,
+        stderr: Option<OutputPipe>,
+    ) {
+        if let Some(stdout) = stdout {
+            self.spawn_pump(handle.clone(), stdout, OutputStream::Stdout);
+        }
+        if let Some(stderr) = stderr {
+            self.spawn_pump(handle, stderr, OutputStream::Stderr);
+        }
+    }
+
+    fn spawn_pump<R>(&self, handle: Arc<AgentHandle>, pipe: R, stream: OutputStream)
+    where
+        R: AsyncRead + Unpin + Send + 'static,
+    {
+        let metrics = self.metrics.clone();
+        tokio::spawn(async move {
+            let mut lines = BufReader::new(pipe).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                let len = line.len();
+                handle.record_output(stream, line);
+                metrics
+                    .total_output_bytes
+                    .fetch_add(len as u64 + 1, Ordering::Relaxed);
+            }
+        });
+    }
+
+    /// Start the background supervisor that reaps idle agents. No-op when no window is configured.
+    pub fn spawn_idle_reaper(&self) -> Option<tokio::task::JoinHandle<()>> {
+        let window = self.idle_reap?;
+        let this = self.clone();
+        let period = (window / 4).clamp(Duration::from_millis(10), Duration::from_secs(30));
+        Some(tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(period);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                ticker.tick().await;
+                this.reap_idle(window).await;
+            }
+        }))
+    }
+
+    /// Stop every non-exempt agent idle for longer than `window`, returning what was reaped.
+    pub async fn reap_idle(&self, window: Duration) -> Vec<ReapRecord> {
+        let now = OffsetDateTime::now_utc();
+        let idle: Vec<(String, Option<String>, time::Duration)> = self
+            .agents
+            .iter()
+            .filter(|e| !e.idle_reap_exempt)
+            .map(|e| (e.id.clone(), e.name.clone(), now - *e.last_used.lock()))
+            .filter(|(_, _, idle_for)| *idle_for > window)
+            .collect();
+        // Stop concurrently so a sweep takes one grace period, not one per agent
+        let mut tasks = tokio::task::JoinSet::new();
+        for (i, (id, name, idle_for)) in idle.into_iter().enumerate() {
+            let this = self.clone();
+            tasks.spawn(async move {
+                let stopped = this.stop(&id, StopSignal::Term, None).await;
+                (i, id, name, idle_for, stopped.is_ok())
             });
         }
+        let mut stopped = Vec::new();
+        while let Some(joined) = tasks.join_next().await {
+            match joined {
+                Ok((i, id, name, idle_for, true)) => stopped.push((i, id, name, idle_for)),
+                // Already stopped by someone else
+                Ok((.., false)) => {}
+                Err(e) => tracing::warn!(error=%e, "idle reap task failed"),
+            }
+        }
+        // Record reaps in the order the agents were found, not the order they exited
+        stopped.sort_by_key(|(i, ..)| *i);
+        let mut records = Vec::new();
+        for (_, id, name, idle_for) in stopped {
+            let idle_secs = idle_for.whole_seconds().max(0) as u64;
+            let record = ReapRecord {
+                agent_id: id,
+                name,
+                reaped_at: OffsetDateTime::now_utc(),
+                idle_secs,
+                reason: format!(
+                    "idle for {idle_secs}s, exceeding the {}s idle reap window",
+                    window.as_secs()
+                ),
+            };
+            tracing::info!(agent_id=%record.agent_id, idle_secs, "reaped idle agent");
+            self.metrics.reaped_count.fetch_add(1, Ordering::Relaxed);
+            {
+                let mut reaped = self.reaped.lock();
+                if reaped.len() >= MAX_REAP_RECORDS {
+                    reaped.pop_front();
+                }
+                reaped.push_back(record.clone());
+            }
+            records.push(record);
+        }
+        records
+    }
+
+    /// Most recent idle reaps, oldest first.
+    pub fn reaped(&self) -> Vec<ReapRecord> {
+        self.reaped.lock().iter().cloned().collect()
     }
 
     pub async fn stop_all(&self) {
         let ids: Vec<String> = self.agents.iter().map(|e| e.id.clone()).collect();
+        // Stop concurrently so shutdown takes one grace period, not one per agent
+        let mut tasks = tokio::task::JoinSet::new();
         for id in ids {
-            let _ = self.stop(&id, StopSignal::Term).await;
+            let this = self.clone();
+            tasks.spawn(async move { this.stop(&id, StopSignal::Term, None).await });
         }
+        while tasks.join_next().await.is_some() {}
+    }
+
+    /// Receive an event whenever an agent is created, removed, produces output or changes status.
+    pub fn subscribe_events(&self) -> broadcast::Receiver<AgentEvent> {
+        self.events.subscribe()
+    }
+
+    pub fn record_cancelled_request(&self) {
+        self.metrics.cancelled_count.fetch_add(1, Ordering::Relaxed);
     }
 
     pub fn metrics_snapshot(&self) -> AgentMetricsSnapshot {
         AgentMetricsSnapshot {
             created_count: self.metrics.created_count.load(Ordering::Relaxed),
             stopped_count: self.metrics.stopped_count.load(Ordering::Relaxed),
+            reaped_count: self.metrics.reaped_count.load(Ordering::Relaxed),
             active_count: self.agents.len() as u64,
             total_input_bytes: self.metrics.total_input_bytes.load(Ordering::Relaxed),
             total_output_bytes: self.metrics.total_output_bytes.load(Ordering::Relaxed),
+            cancelled_count: self.metrics.cancelled_count.load(Ordering::Relaxed),
+            recently_reaped: self.reaped(),
         }
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum StopSignal {
     Term,
     Kill,
 }
 
+fn spawn_child(
+    bin: &str,
+    args: &[String],
+    working_dir: Option<&PathBuf>,
+    env: &HashMap<String, String>,
+    limits: &ResourceLimits,
+    pty: Option<PtySize>,
+) -> Result<(Child, Option<process::Pty>), AgentError> {
+    let mut cmd = Command::new(bin);
+    cmd.args(args);
+    if let Some(dir) = working_dir {
+        cmd.current_dir(dir);
+    }
+    // Allowlist env pass-through: only explicit provided entries
+    if !env.is_empty() {
+        cmd.envs(env);
+    }
+    // Own process group so stop/reset can reach shells, language servers and dev servers it spawns.
+    // A pty child gets one by leading its own session instead.
+    let pty = match pty {
+        Some(size) => Some(process::attach_pty(&mut cmd, size)?),
+        None => {
+            process::isolate_process_group(&mut cmd);
+            cmd.stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped());
+            None
+        }
+    };
+    process::apply_resource_limits(&mut cmd, limits)?;
+    let child = cmd
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| AgentError::Spawn(format!("Failed to spawn cursor-agent: {}. Ensure cursor-agent is installed and on PATH or set CURSOR_AGENT_PATH.", e)))?;
+    Ok((child, pty))
+}
+
+// Bring the process tree down according to `signal`, waiting on the waiter task's exit notification
+async fn terminate(handle: &AgentHandle, signal: StopSignal, grace: Duration) -> StopOutcome {
+    let (pgid, mut exit, group_outlived, kill) = {
+        let process = handle.process.lock();
+        (
+            process.pid,
+            process.exit.clone(),
+            process.group_outlived.clone(),
+            process.kill.clone(),
+        )
+    };
+    let deadline = std::time::Instant::now() + grace;
+    let exited = *exit.borrow();
+    let (path, info, sweep) = match exited {
+        // Leave a group that was already empty alone; its id may belong to someone else by now
+        Some(info) => (
+            StopPath::AlreadyExited,
+            Some(info),
+            group_outlived.load(Ordering::SeqCst),
+        ),
+        None => {
+            let (path, info) =
+                terminate_leader(&handle.id, pgid, &mut exit, &kill, signal, grace).await;
+            (path, info, true)
+        }
+    };
+    // Give the rest of the tree what is left of the grace period, then kill whatever remains
+    let stragglers = match pgid.filter(|_| sweep) {
+        Some(pgid) => {
+            let settle = deadline
+                .saturating_duration_since(std::time::Instant::now())
+                .max(Duration::from_millis(100));
+            sweep_group(pgid, settle).await
+        }
+        None => Vec::new(),
+    };
+    StopOutcome {
+        path,
+        exit_code: info.and_then(|i| i.code),
+        exit_signal: info.and_then(|i| i.signal),
+        stragglers,
+    }
+}
+
+async fn terminate_leader(
+    agent_id: &str,
+    pgid: Option<u32>,
+    exit: &mut watch::Receiver<Option<ExitInfo>>,
+    kill: &Notify,
+    signal: StopSignal,
+    grace: Duration,
+) -> (StopPath, Option<ExitInfo>) {
+    let mut path = StopPath::Killed;
+    if let StopSignal::Term = signal {
+        match pgid.map(process::request_terminate) {
+            Some(Ok(())) => {
+                if let Some(info) = wait_for_exit(exit, grace).await {
+                    return (StopPath::Terminated, Some(info));
+                }
+                tracing::warn!(
+                    agent_id,
+                    grace_ms = grace.as_millis() as u64,
+                    "agent ignored SIGTERM; escalating to SIGKILL"
+                );
+                path = StopPath::Escalated;
+            }
+            Some(Err(e)) => {
+                tracing::debug!(agent_id, error=%e, "SIGTERM unavailable; killing");
+            }
+            None => {}
+        }
+    }
+    if let Some(pgid) = pgid {
+        process::kill_group(pgid);
+    }
+    kill.notify_one();
+    (path, wait_for_exit(exit, KILL_WAIT).await)
+}
+
+// Wait up to `settle` for the group to empty; SIGKILL and report any processes still in it.
+async fn sweep_group(pgid: u32, settle: Duration) -> Vec<u32> {
+    let deadline = std::time::Instant::now() + settle;
+    let mut members = process::group_members(pgid);
+    while !members.is_empty() && std::time::Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        members = process::group_members(pgid);
+    }
+    if members.is_empty() {
+        return members;
+    }
+    tracing::warn!(pgid, stragglers=?members, "process group outlived the agent; killing stragglers");
+    process::kill_group(pgid);
+    let deadline = std::time::Instant::now() + KILL_WAIT;
+    while !process::group_members(pgid).is_empty() && std::time::Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    members
+}
+
+// What, besides a match, ends an output wait
+struct OutputWatch<'a> {
+    filter: &'a OutputFilter,
+    idle: Option<Duration>,
+    until_exit: bool,
+    deadline: tokio::time::Instant,
+}
+
+impl OutputWatch<'_> {
+    // Feed the agent's records from `cursor` on to `scan` as they arrive, advancing the cursor past
+    // them, until `scan` returns a value. `scan` may move the cursor back to leave records for a
+    // later read. Otherwise ends with why it gave up. Wakes on notifications from the pumps and the
+    // exit waiter.
+    async fn run<T>(
+        &self,
+        handle: &AgentHandle,
+        cursor: &mut u64,
+        mut scan: impl FnMut(Vec<OutputRecord>, &mut u64) -> Option<T>,
+    ) -> Result<T, WaitReason> {
+        let mut output = handle.output_seq.subscribe();
+        let mut state = handle.state.subscribe();
+        let mut last_output = tokio::time::Instant::now();
+        let mut drained_at = None;
+        loop {
+            // Mark both as seen before looking, so a change made meanwhile still wakes us below
+            output.borrow_and_update();
+            let status = state.borrow_and_update().status;
+            let page = handle
+                .buffer
+                .lock()
+                .read_from(*cursor, usize::MAX, self.filter);
+            *cursor = page.next_cursor;
+            if let Some(found) = scan(page.records, cursor) {
+                return Ok(found);
+            }
+            let exited = matches!(
+                status,
+                AgentStatus::Exited(_) | AgentStatus::Signaled(_) | AgentStatus::Stopped
+            );
+            // A stopped agent will not write anything more, whatever was asked for. The pumps may
+            // still be draining the pipes when the exit is seen, so give them a moment
+            if (self.until_exit && exited) || status == AgentStatus::Stopped {
+                drained_at.get_or_insert_with(|| tokio::time::Instant::now() + EXIT_DRAIN);
+            }
+            let idle_at = self.idle.map(|idle| last_output + idle);
+            tokio::select! {
+                _ = output.changed() => last_output = tokio::time::Instant::now(),
+                _ = state.changed() => {}
+                _ = tokio::time::sleep_until(drained_at.unwrap_or(self.deadline)), if drained_at.is_some() => {
+                    return Err(WaitReason::Exited);
+                }
+                _ = tokio::time::sleep_until(idle_at.unwrap_or(self.deadline)), if idle_at.is_some() => {
+                    return Err(WaitReason::Idle);
+                }
+                _ = tokio::time::sleep_until(self.deadline) => return Err(WaitReason::Timeout),
+            }
+        }
+    }
+}
+
+async fn wait_for_exit(
+    exit: &mut watch::Receiver<Option<ExitInfo>>,
+    timeout: Duration,
+) -> Option<ExitInfo> {
+    if let Ok(Ok(info)) = tokio::time::timeout(timeout, exit.wait_for(|e| e.is_some())).await {
+        return *info;
+    }
+    *exit.borrow()
+}
+
 impl AgentManagerImpl {
     pub fn resolve_binary_path(&self) -> Result<String, AgentError> {
         self.resolve_binary()
@@ -319,9 +1367,14 @@ impl AgentManagerImpl {
 
 impl Drop for AgentHandle {
     fn drop(&mut self) {
-        // Best-effort termination to avoid zombie processes
-        if let Some(_id) = self.child.get_mut().id() {
-            let _ = self.child.get_mut().start_kill();
+        // Best-effort termination of the whole tree to avoid orphans and zombie processes.
+        // Only while the child is unreaped, so a recycled process group id is never signalled.
+        let process = self.process.get_mut();
+        if process.exit.borrow().is_none() {
+            if let Some(pgid) = process.pid {
+                process::kill_group(pgid);
+            }
+            process.kill.notify_one();
         }
     }
 }
@@ -330,17 +1383,23 @@ impl Drop for AgentHandle {
 pub struct AgentMetrics {
     pub created_count: AtomicU64,
     pub stopped_count: AtomicU64,
+    pub reaped_count: AtomicU64,
     pub total_input_bytes: AtomicU64,
     pub total_output_bytes: AtomicU64,
+    pub cancelled_count: AtomicU64,
 }
 
 #[derive(serde::Serialize)]
 pub struct AgentMetricsSnapshot {
     pub created_count: u64,
     pub stopped_count: u64,
+    pub reaped_count: u64,
     pub active_count: u64,
     pub total_input_bytes: u64,
     pub total_output_bytes: u64,
+    /// Requests aborted by the client via notifications/cancelled
+    pub cancelled_count: u64,
+    pub recently_reaped: Vec<ReapRecord>,
 }
 
 #[cfg(test)]
@@ -377,9 +1436,8 @@ mod tests {
         let manager = AgentManagerImpl::new(Some(test_bin()), 64 * 1024);
         let req = CreateAgentRequest {
             name: Some("t1".into()),
-            working_dir: None,
-            env: Default::default(),
             args: test_args(),
+            ..Default::default()
         };
         let created = manager.create(req).await.expect("create");
         manager
@@ -387,7 +1445,10 @@ mod tests {
             .await
             .expect("send");
         sleep(Duration::from_millis(100)).await;
-        let buf = manager.get_buffer(&created.agent_id).await.expect("buffer");
+        let buf = manager
+            .get_buffer(&created.agent_id, &OutputFilter::default())
+            .await
+            .expect("buffer");
         assert!(buf.contains("hello world"));
     }
 
@@ -396,10 +1457,8 @@ mod tests {
         let manager = AgentManagerImpl::new(Some(test_bin()), 64 * 1024);
         let created = manager
             .create(CreateAgentRequest {
-                name: None,
-                working_dir: None,
-                env: Default::default(),
                 args: test_args(),
+                ..Default::default()
             })
             .await
             .unwrap();
@@ -409,19 +1468,58 @@ mod tests {
             .unwrap();
         sleep(Duration::from_millis(100)).await;
         manager.reset(&created.agent_id, false).await.unwrap();
-        let buf = manager.get_buffer(&created.agent_id).await.unwrap();
+        let buf = manager
+            .get_buffer(&created.agent_id, &OutputFilter::default())
+            .await
+            .unwrap();
         assert!(buf.trim().is_empty());
     }
 
+    #[tokio::test]
+    async fn read_output_cursor_survives_soft_reset() {
+        let manager = AgentManagerImpl::new(Some(test_bin()), 64 * 1024);
+        let created = manager
+            .create(CreateAgentRequest {
+                args: test_args(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        manager.send_input(&created.agent_id, "one").await.unwrap();
+        manager.send_input(&created.agent_id, "two").await.unwrap();
+        sleep(Duration::from_millis(100)).await;
+        let all = OutputFilter::default();
+        let page = manager
+            .read_output(&created.agent_id, 0, 10, &all)
+            .await
+            .unwrap();
+        let texts: Vec<_> = page.records.iter().map(|r| r.text.as_str()).collect();
+        assert_eq!(texts, vec!["one", "two"]);
+        assert_eq!(page.next_cursor, 2);
+
+        manager.reset(&created.agent_id, false).await.unwrap();
+        manager
+            .send_input(&created.agent_id, "three")
+            .await
+            .unwrap();
+        sleep(Duration::from_millis(100)).await;
+        let page = manager
+            .read_output(&created.agent_id, page.next_cursor, 10, &all)
+            .await
+            .unwrap();
+        assert_eq!(page.records.len(), 1);
+        assert_eq!(page.records[0].seq, 2);
+        assert_eq!(page.records[0].text, "three");
+        assert_eq!(page.evicted, 0);
+    }
+
     #[tokio::test]
     async fn lifecycle_reset_hard_respawns_process() {
         let manager = AgentManagerImpl::new(Some(test_bin()), 64 * 1024);
         let created = manager
             .create(CreateAgentRequest {
-                name: None,
-                working_dir: None,
-                env: Default::default(),
                 args: test_args(),
+                ..Default::default()
             })
             .await
             .unwrap();
@@ -445,17 +1543,9 @@ mod tests {
     #[tokio::test]
     async fn lifecycle_stop_removes_agent() {
         let manager = AgentManagerImpl::new(Some(test_bin()), 64 * 1024);
-        let created = manager
-            .create(CreateAgentRequest {
-                name: None,
-                working_dir: None,
-                env: Default::default(),
-                args: vec![],
-            })
-            .await
-            .unwrap();
+        let created = manager.create(CreateAgentRequest::default()).await.unwrap();
         manager
-            .stop(&created.agent_id, StopSignal::Term)
+            .stop(&created.agent_id, StopSignal::Term, None)
             .await
             .unwrap();
         sleep(Duration::from_millis(100)).await;
@@ -469,4 +1559,819 @@ mod tests {
         let snap = manager.metrics_snapshot();
         assert!(snap.stopped_count >= 1);
     }
+
+    #[tokio::test]
+    async fn idle_reaper_stops_idle_agents_and_spares_exempt_ones() {
+        let manager = AgentManagerImpl::new(Some(test_bin()), 64 * 1024)
+            .with_idle_reap(Some(Duration::from_millis(100)));
+        let idle = manager
+            .create(CreateAgentRequest {
+                name: Some("idle".into()),
+                args: test_args(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        let exempt = manager
+            .create(CreateAgentRequest {
+                name: Some("exempt".into()),
+                args: test_args(),
+                idle_reap_exempt: true,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        let reaper = manager.spawn_idle_reaper().expect("reaper enabled");
+        sleep(Duration::from_millis(400)).await;
+        reaper.abort();
+
+        let ids: Vec<_> = manager
+            .list()
+            .await
+            .into_iter()
+            .map(|a| a.agent_id)
+            .collect();
+        assert!(!ids.contains(&idle.agent_id));
+        assert!(ids.contains(&exempt.agent_id));
+
+        let snap = manager.metrics_snapshot();
+        assert_eq!(snap.reaped_count, 1);
+        assert_eq!(snap.recently_reaped[0].agent_id, idle.agent_id);
+        assert!(snap.recently_reaped[0].reason.contains("idle"));
+    }
+
+    #[cfg(unix)]
+    fn shell_agent(script: &str) -> CreateAgentRequest {
+        CreateAgentRequest {
+            args: vec!["-c".into(), script.into()],
+            ..Default::default()
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn idle_sweeps_stop_agents_concurrently() {
+        let manager = AgentManagerImpl::new(Some("/bin/sh".into()), 64 * 1024)
+            .with_stop_grace(Duration::from_millis(500));
+        let mut ids = Vec::new();
+        for _ in 0..4 {
+            // Ignores SIGTERM, so each stop waits out the full grace period
+            let created = manager
+                .create(shell_agent("trap '' TERM; while :; do sleep 0.05; done"))
+                .await
+                .unwrap();
+            ids.push(created.agent_id);
+        }
+        sleep(Duration::from_millis(50)).await;
+        let started = std::time::Instant::now();
+        let records = manager.reap_idle(Duration::from_millis(10)).await;
+        assert!(started.elapsed() < Duration::from_millis(1500));
+        let reaped: Vec<_> = records.into_iter().map(|r| r.agent_id).collect();
+        assert_eq!(reaped.len(), ids.len());
+        assert!(ids.iter().all(|id| reaped.contains(id)));
+        assert!(manager.list().await.is_empty());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn stop_term_exits_gracefully_within_grace() {
+        let manager = AgentManagerImpl::new(Some("/bin/sh".into()), 64 * 1024);
+        let created = manager
+            .create(shell_agent(
+                "trap 'exit 3' TERM; while :; do sleep 0.05; done",
+            ))
+            .await
+            .unwrap();
+        sleep(Duration::from_millis(100)).await;
+        let outcome = manager
+            .stop(
+                &created.agent_id,
+                StopSignal::Term,
+                Some(Duration::from_secs(3)),
+            )
+            .await
+            .unwrap();
+        assert_eq!(outcome.path, StopPath::Terminated);
+        assert_eq!(outcome.exit_code, Some(3));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn stop_term_escalates_to_kill_after_grace() {
+        let manager = AgentManagerImpl::new(Some("/bin/sh".into()), 64 * 1024)
+            .with_stop_grace(Duration::from_millis(200));
+        let created = manager
+            .create(shell_agent("trap '' TERM; while :; do sleep 0.05; done"))
+            .await
+            .unwrap();
+        sleep(Duration::from_millis(100)).await;
+        let outcome = manager
+            .stop(&created.agent_id, StopSignal::Term, None)
+            .await
+            .unwrap();
+        assert_eq!(outcome.path, StopPath::Escalated);
+        assert_eq!(outcome.exit_signal, Some(libc::SIGKILL));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn stop_kills_grandchildren_and_reports_stragglers() {
+        let manager = AgentManagerImpl::new(Some("/bin/sh".into()), 64 * 1024);
+        // Background child ignores SIGTERM and outlives the agent's own process
+        let created = manager
+            .create(shell_agent(
+                "(trap '' TERM; exec sleep 30) & echo $!; exec sleep 30",
+            ))
+            .await
+            .unwrap();
+        sleep(Duration::from_millis(200)).await;
+        let grandchild: u32 = manager
+            .get_buffer(&created.agent_id, &OutputFilter::default())
+            .await
+            .unwrap()
+            .trim()
+            .parse()
+            .expect("grandchild pid");
+        assert!(process::group_members(created.pid).contains(&grandchild));
+
+        let outcome = manager
+            .stop(
+                &created.agent_id,
+                StopSignal::Term,
+                Some(Duration::from_millis(300)),
+            )
+            .await
+            .unwrap();
+        assert_eq!(outcome.path, StopPath::Terminated);
+        assert_eq!(outcome.stragglers, vec![grandchild]);
+        assert!(process::group_members(created.pid).is_empty());
+
+        // A group left behind at exit is still swept after the fact
+        let created = manager
+            .create(shell_agent("(trap '' TERM; exec sleep 30) & echo $!"))
+            .await
+            .unwrap();
+        let exit = WaitCondition {
+            exit: true,
+            ..Default::default()
+        };
+        manager
+            .wait_for_output(&created.agent_id, None, &exit, Duration::from_secs(5))
+            .await
+            .unwrap();
+        let grandchild: u32 = manager
+            .get_buffer(&created.agent_id, &OutputFilter::default())
+            .await
+            .unwrap()
+            .trim()
+            .parse()
+            .expect("grandchild pid");
+        let outcome = manager
+            .stop(
+                &created.agent_id,
+                StopSignal::Kill,
+                Some(Duration::from_millis(100)),
+            )
+            .await
+            .unwrap();
+        assert_eq!(outcome.path, StopPath::AlreadyExited);
+        assert_eq!(outcome.stragglers, vec![grandchild]);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn resource_limits_apply_to_child_and_show_in_metadata() {
+        let manager = AgentManagerImpl::new(Some("/bin/sh".into()), 64 * 1024);
+        let limits = ResourceLimits {
+            open_files: Some(64),
+            core_bytes: Some(0),
+            ..Default::default()
+        };
+        let created = manager
+            .create(CreateAgentRequest {
+                limits: limits.clone(),
+                ..shell_agent("ulimit -n; ulimit -c; exec cat")
+            })
+            .await
+            .unwrap();
+        sleep(Duration::from_millis(200)).await;
+        let buf = manager
+            .get_buffer(&created.agent_id, &OutputFilter::default())
+            .await
+            .unwrap();
+        assert_eq!(buf.lines().collect::<Vec<_>>(), vec!["64", "0"]);
+        let info = manager
+            .list()
+            .await
+            .into_iter()
+            .find(|a| a.agent_id == created.agent_id)
+            .unwrap();
+        assert_eq!(info.limits, limits);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn status_tracks_running_then_exit_code() {
+        let manager = AgentManagerImpl::new(Some("/bin/sh".into()), 64 * 1024);
+        let created = manager
+            .create(shell_agent("read line; exit 7"))
+            .await
+            .unwrap();
+        let info = |list: Vec<AgentInfo>| {
+            list.into_iter()
+                .find(|a| a.agent_id == created.agent_id)
+                .unwrap()
+        };
+        let running = info(manager.list().await);
+        assert_eq!(running.status, "running");
+        assert!(running.exited_at.is_none());
+
+        manager.send_input(&created.agent_id, "go").await.unwrap();
+        sleep(Duration::from_millis(200)).await;
+        let exited = info(manager.list().await);
+        assert_eq!(exited.status, "exited");
+        assert_eq!(exited.exit_code, Some(7));
+        assert!(exited.exited_at.is_some());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn status_tracks_termination_by_signal() {
+        let manager = AgentManagerImpl::new(Some("/bin/sh".into()), 64 * 1024);
+        let created = manager.create(shell_agent("kill -KILL $$")).await.unwrap();
+        sleep(Duration::from_millis(200)).await;
+        let info = manager
+            .list()
+            .await
+            .into_iter()
+            .find(|a| a.agent_id == created.agent_id)
+            .unwrap();
+        assert_eq!(info.status, "signaled");
+        assert_eq!(info.exit_signal, Some(libc::SIGKILL));
+        assert_eq!(info.exit_code, None);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn wait_for_output_returns_on_match_idle_exit_or_timeout() {
+        let manager = AgentManagerImpl::new(Some("/bin/sh".into()), 64 * 1024);
+        let created = manager
+            .create(shell_agent(
+                "echo boot; read x; echo got $x; sleep 0.2; echo tail; read y; exit 3",
+            ))
+            .await
+            .unwrap();
+        let id = &created.agent_id;
+        let timeout = Duration::from_secs(5);
+        let pattern = |re: &str| WaitCondition {
+            pattern: Some(regex::Regex::new(re).unwrap()),
+            ..Default::default()
+        };
+
+        // A cursor catches output written before the call
+        let booted = manager
+            .wait_for_output(id, Some(0), &pattern("^boot$"), timeout)
+            .await
+            .unwrap();
+        assert_eq!(booted.reason, WaitReason::Matched);
+        manager.send_input(id, "hi").await.unwrap();
+        let got = manager
+            .wait_for_output(
+                id,
+                Some(booted.next_cursor),
+                &pattern(r"got (\w+)"),
+                timeout,
+            )
+            .await
+            .unwrap();
+        assert_eq!(got.reason, WaitReason::Matched);
+        assert_eq!(got.matches.len(), 1);
+        assert_eq!(got.matches[0].text, "got hi");
+
+        // Idle counts from the last line, so the delayed "tail" pushes it back
+        let idle = WaitCondition {
+            idle: Some(Duration::from_millis(300)),
+            ..Default::default()
+        };
+        let quiet = manager
+            .wait_for_output(id, None, &idle, timeout)
+            .await
+            .unwrap();
+        assert_eq!(quiet.reason, WaitReason::Idle);
+        assert!(quiet.waited_ms >= 300, "waited {}ms", quiet.waited_ms);
+        assert_eq!(quiet.next_cursor, got.next_cursor + 1);
+
+        let never = manager
+            .wait_for_output(id, None, &pattern("never"), Duration::from_millis(100))
+            .await
+            .unwrap();
+        assert_eq!(never.reason, WaitReason::Timeout);
+        assert!(never.matches.is_empty());
+
+        manager.send_input(id, "bye").await.unwrap();
+        let exit = WaitCondition {
+            exit: true,
+            ..Default::default()
+        };
+        let exited = manager
+            .wait_for_output(id, None, &exit, timeout)
+            .await
+            .unwrap();
+        assert_eq!(exited.reason, WaitReason::Exited);
+        assert_eq!(exited.status, "exited");
+        assert!(matches!(
+            manager
+                .wait_for_output("missing", None, &exit, timeout)
+                .await,
+            Err(AgentError::NotFound(_))
+        ));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn send_and_expect_runs_scripts_atomically() {
+        let manager = AgentManagerImpl::new(Some("/bin/sh".into()), 64 * 1024);
+        let created = manager
+            .create(shell_agent(
+                r#"while read l; do sleep 0.05; echo "> $l"; [ "$l" = quit ] && exit 0; done"#,
+            ))
+            .await
+            .unwrap();
+        let id = created.agent_id.clone();
+        let step = |send: &str, expect: &[&str], timeout_ms: u64| ExpectStep {
+            send: Some(send.to_string()),
+            expect: expect
+                .iter()
+                .map(|p| regex::Regex::new(p).unwrap())
+                .collect(),
+            timeout: Duration::from_millis(timeout_ms),
+        };
+
+        // Input sent while a script runs waits for the script to finish
+        let script = tokio::spawn({
+            let manager = manager.clone();
+            let steps = vec![
+                step("a", &["^> a$"], 5000),
+                step("b", &["nope", "^> (b)$"], 5000),
+            ];
+            async move { manager.send_and_expect(&id, &steps).await.unwrap() }
+        });
+        sleep(Duration::from_millis(20)).await;
+        manager.send_input(&created.agent_id, "c").await.unwrap();
+        let outcome = script.await.unwrap();
+        assert!(outcome.completed);
+        assert_eq!(outcome.steps.len(), 2);
+        assert_eq!(outcome.steps[0].matched, Some(0));
+        let texts: Vec<_> = outcome.steps[0].output.iter().map(|r| &r.text).collect();
+        assert_eq!(texts, ["> a"]);
+        assert_eq!(outcome.steps[1].matched, Some(1));
+        assert_eq!(outcome.steps[1].pattern.as_deref(), Some("^> (b)$"));
+        assert_eq!(outcome.steps[1].output[0].text, "> b");
+        let rest = manager
+            .wait_for_output(
+                &created.agent_id,
+                Some(outcome.next_cursor),
+                &WaitCondition {
+                    pattern: Some(regex::Regex::new("^> ").unwrap()),
+                    ..Default::default()
+                },
+                Duration::from_secs(5),
+            )
+            .await
+            .unwrap();
+        assert_eq!(rest.matches[0].text, "> c");
+
+        // A script stops at its first unmatched step
+        let steps = vec![step("d", &["never"], 300), step("e", &["^> e$"], 5000)];
+        let outcome = manager
+            .send_and_expect(&created.agent_id, &steps)
+            .await
+            .unwrap();
+        assert!(!outcome.completed);
+        assert_eq!(outcome.steps.len(), 1);
+        assert_eq!(outcome.steps[0].reason, WaitReason::Timeout);
+        assert_eq!(outcome.steps[0].output[0].text, "> d");
+
+        let steps = vec![step("quit", &["never"], 5000)];
+        let outcome = manager
+            .send_and_expect(&created.agent_id, &steps)
+            .await
+            .unwrap();
+        assert_eq!(outcome.steps[0].reason, WaitReason::Exited);
+        assert_eq!(outcome.steps[0].output[0].text, "> quit");
+        assert_eq!(outcome.status, "exited");
+
+        // Input that cannot be written ends the script with what ran so far
+        let closed = manager
+            .create(shell_agent("sleep 0.2; echo ready; sleep 5"))
+            .await
+            .unwrap();
+        assert!(manager.close_stdin(&closed.agent_id).await.unwrap());
+        let steps = vec![
+            ExpectStep {
+                send: None,
+                ..step("", &["^ready$"], 5000)
+            },
+            step("more", &["never"], 1000),
+        ];
+        let outcome = manager
+            .send_and_expect(&closed.agent_id, &steps)
+            .await
+            .unwrap();
+        assert!(!outcome.completed);
+        assert_eq!(outcome.steps.len(), 2);
+        assert_eq!(outcome.steps[0].reason, WaitReason::Matched);
+        assert_eq!(outcome.steps[1].reason, WaitReason::SendFailed);
+        assert!(outcome.steps[1].error.is_some());
+        assert!(outcome.steps[1].output.is_empty());
+        manager
+            .stop(&closed.agent_id, StopSignal::Kill, None)
+            .await
+            .unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn pty_agents_get_a_resizable_terminal() {
+        let manager = AgentManagerImpl::new(Some("/bin/sh".into()), 64 * 1024);
+        let created = manager
+            .create(CreateAgentRequest {
+                pty: Some(PtySize { rows: 30, cols: 100 }),
+                ..shell_agent(
+                    r#"[ -t 0 ] && [ -t 1 ] && echo "on a tty"; echo "oops" >&2; echo "size $(stty size)"; while read l; do echo "got $l $(stty size)"; done; echo eof"#,
+                )
+            })
+            .await
+            .unwrap();
+        let id = &created.agent_id;
+        let until = |re: &str| {
+            let cond = WaitCondition {
+                pattern: Some(regex::Regex::new(re).unwrap()),
+                ..Default::default()
+            };
+            let manager = manager.clone();
+            let id = id.clone();
+            async move {
+                manager
+                    .wait_for_output(&id, Some(0), &cond, Duration::from_secs(5))
+                    .await
+                    .unwrap()
+                    .reason
+            }
+        };
+
+        assert_eq!(until("^on a tty$").await, WaitReason::Matched);
+        assert_eq!(until("^size 30 100$").await, WaitReason::Matched);
+        // The terminal merges stderr into the output stream
+        let page = manager
+            .read_output(id, 0, 100, &OutputFilter::default())
+            .await
+            .unwrap();
+        assert!(page
+            .records
+            .iter()
+            .all(|r| r.stream == OutputStream::Stdout));
+        assert!(page.records.iter().any(|r| r.text == "oops"));
+
+        let info = manager
+            .resize_pty(
+                id,
+                PtySize {
+                    rows: 40,
+                    cols: 120,
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            info.pty,
+            Some(PtySize {
+                rows: 40,
+                cols: 120
+            })
+        );
+        manager.send_input(id, "x").await.unwrap();
+        assert_eq!(until("^got x 40 120$").await, WaitReason::Matched);
+        assert!(manager.close_stdin(id).await.unwrap());
+        assert_eq!(until("^eof$").await, WaitReason::Matched);
+
+        // A hard reset opens the new terminal at the current size
+        manager.reset(id, true).await.unwrap();
+        assert_eq!(until("^size 40 120$").await, WaitReason::Matched);
+        manager.stop(id, StopSignal::Kill, None).await.unwrap();
+
+        let piped = manager.create(shell_agent("exec cat")).await.unwrap();
+        assert!(matches!(
+            manager
+                .resize_pty(&piped.agent_id, PtySize::default())
+                .await,
+            Err(AgentError::InvalidState(_))
+        ));
+        manager
+            .stop(&piped.agent_id, StopSignal::Kill, None)
+            .await
+            .unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn pty_descriptors_do_not_leak_into_other_agents() {
+        let manager = AgentManagerImpl::new(Some("/bin/sh".into()), 64 * 1024);
+        let tty = manager
+            .create(CreateAgentRequest {
+                pty: Some(PtySize::default()),
+                ..shell_agent("exec cat")
+            })
+            .await
+            .unwrap();
+        let lister = manager
+            .create(shell_agent(
+                r#"for f in /proc/$$/fd/*; do readlink "$f"; done; echo done"#,
+            ))
+            .await
+            .unwrap();
+        let cond = WaitCondition {
+            pattern: Some(regex::Regex::new("^done$").unwrap()),
+            ..Default::default()
+        };
+        let waited = manager
+            .wait_for_output(&lister.agent_id, Some(0), &cond, Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert_eq!(waited.reason, WaitReason::Matched);
+        let page = manager
+            .read_output(&lister.agent_id, 0, 100, &OutputFilter::default())
+            .await
+            .unwrap();
+        assert!(
+            page.records
+                .iter()
+                .all(|r| !r.text.contains("ptmx") && !r.text.starts_with("/dev/pts/")),
+            "{:?}",
+            page.records
+        );
+        manager
+            .stop(&tty.agent_id, StopSignal::Kill, None)
+            .await
+            .unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn signals_pause_resume_and_stdin_close() {
+        let manager = AgentManagerImpl::new(Some("/bin/sh".into()), 64 * 1024);
+        let created = manager
+            .create(shell_agent(
+                r#"while read l; do echo "> $l"; done; echo eof"#,
+            ))
+            .await
+            .unwrap();
+        let id = &created.agent_id;
+        let until = |re: &str, ms: u64| {
+            let cond = WaitCondition {
+                pattern: Some(regex::Regex::new(re).unwrap()),
+                ..Default::default()
+            };
+            let manager = manager.clone();
+            let id = id.clone();
+            async move {
+                manager
+                    .wait_for_output(&id, Some(0), &cond, Duration::from_millis(ms))
+                    .await
+                    .unwrap()
+                    .reason
+            }
+        };
+
+        // Partial writes are not line-buffered by the server
+        manager.send_bytes(id, b"par").await.unwrap();
+        manager.send_bytes(id, b"tial\n").await.unwrap();
+        assert_eq!(until("^> partial$", 5000).await, WaitReason::Matched);
+
+        let info = manager.signal(id, AgentSignal::Stop).await.unwrap();
+        assert_eq!(info.status, "paused");
+        manager.send_input(id, "while paused").await.unwrap();
+        assert_eq!(until("while paused", 200).await, WaitReason::Timeout);
+        let info = manager.signal(id, AgentSignal::Cont).await.unwrap();
+        assert_eq!(info.status, "running");
+        assert_eq!(until("^> while paused$", 5000).await, WaitReason::Matched);
+
+        assert!(manager.close_stdin(id).await.unwrap());
+        assert_eq!(until("^eof$", 5000).await, WaitReason::Matched);
+        assert!(!manager.close_stdin(id).await.unwrap());
+        assert!(matches!(
+            manager.send_input(id, "late").await,
+            Err(AgentError::InvalidState(_))
+        ));
+        let exit = WaitCondition {
+            exit: true,
+            ..Default::default()
+        };
+        manager
+            .wait_for_output(id, None, &exit, Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert!(matches!(
+            manager.signal(id, AgentSignal::Int).await,
+            Err(AgentError::InvalidState(_))
+        ));
+
+        // SIGINT interrupts a runaway command; a paused agent still stops gracefully
+        let busy = manager.create(shell_agent("sleep 30")).await.unwrap();
+        manager
+            .signal(&busy.agent_id, AgentSignal::Int)
+            .await
+            .unwrap();
+        manager
+            .wait_for_output(&busy.agent_id, None, &exit, Duration::from_secs(5))
+            .await
+            .unwrap();
+        let info = manager.info(&busy.agent_id).await.unwrap();
+        assert_eq!(info.exit_signal, Some(libc::SIGINT));
+        let paused = manager.create(shell_agent("sleep 30")).await.unwrap();
+        manager
+            .signal(&paused.agent_id, AgentSignal::Stop)
+            .await
+            .unwrap();
+        let outcome = manager
+            .stop(
+                &paused.agent_id,
+                StopSignal::Term,
+                Some(Duration::from_secs(2)),
+            )
+            .await
+            .unwrap();
+        assert_eq!(outcome.path, StopPath::Terminated);
+        manager.stop_all().await;
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn restart_policy_restarts_failures_until_crash_loop() {
+        let manager = AgentManagerImpl::new(Some("/bin/sh".into()), 64 * 1024);
+        let created = manager
+            .create(CreateAgentRequest {
+                restart_policy: RestartPolicy {
+                    mode: RestartMode::OnFailure,
+                    max_retries: 2,
+                    initial_backoff_ms: 20,
+                    max_backoff_ms: 50,
+                },
+                ..shell_agent("echo boot; exit 1")
+            })
+            .await
+            .unwrap();
+        sleep(Duration::from_millis(600)).await;
+        let info = manager
+            .list()
+            .await
+            .into_iter()
+            .find(|a| a.agent_id == created.agent_id)
+            .unwrap();
+        assert_eq!(info.restart_count, 2);
+        assert!(info.crash_loop);
+        assert_eq!(info.status, "exited");
+        assert_eq!(info.exit_code, Some(1));
+        // Output from every run is kept across automatic restarts
+        let buf = manager
+            .get_buffer(&created.agent_id, &OutputFilter::default())
+            .await
+            .unwrap();
+        assert_eq!(buf.matches("boot").count(), 3);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn restart_policy_on_failure_ignores_clean_exit() {
+        let manager = AgentManagerImpl::new(Some("/bin/sh".into()), 64 * 1024);
+        let created = manager
+            .create(CreateAgentRequest {
+                restart_policy: RestartPolicy {
+                    mode: RestartMode::OnFailure,
+                    initial_backoff_ms: 20,
+                    ..Default::default()
+                },
+                ..shell_agent("exit 0")
+            })
+            .await
+            .unwrap();
+        sleep(Duration::from_millis(200)).await;
+        let info = manager
+            .list()
+            .await
+            .into_iter()
+            .find(|a| a.agent_id == created.agent_id)
+            .unwrap();
+        assert_eq!(info.restart_count, 0);
+        assert!(!info.crash_loop);
+        assert_eq!(info.status, "exited");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn transcript_keeps_output_evicted_from_buffer() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = AgentManagerImpl::new(Some("/bin/sh".into()), 64).with_transcripts(Some(
+            TranscriptConfig {
+                dir: dir.path().to_path_buf(),
+                max_bytes: 1024 * 1024,
+                max_files: 2,
+            },
+        ));
+        let created = manager
+            .create(shell_agent(
+                "for i in 1 2 3 4 5 6 7 8 9 10; do echo line-$i; done; echo oops >&2",
+            ))
+            .await
+            .unwrap();
+        sleep(Duration::from_millis(300)).await;
+        let buf = manager
+            .get_buffer(&created.agent_id, &OutputFilter::default())
+            .await
+            .unwrap();
+        assert!(!buf.contains("line-1\n"));
+
+        manager
+            .stop(&created.agent_id, StopSignal::Kill, None)
+            .await
+            .unwrap();
+        // Still readable after the agent is gone
+        let all = OutputFilter::default();
+        let page = manager
+            .read_transcript(&created.agent_id, 0, usize::MAX, &all)
+            .await
+            .unwrap();
+        assert_eq!(page.records.len(), 11);
+        assert_eq!(page.records[0].text, "line-1");
+        let err = page
+            .records
+            .iter()
+            .find(|r| r.stream == OutputStream::Stderr)
+            .unwrap();
+        assert_eq!(err.text, "oops");
+        assert!(manager
+            .read_transcript("../etc/passwd", 0, 10, &all)
+            .await
+            .is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn terminal_output_is_normalized_with_raw_kept_on_request() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = AgentManagerImpl::new(Some("/bin/sh".into()), 64 * 1024).with_transcripts(
+            Some(TranscriptConfig {
+                dir: dir.path().to_path_buf(),
+                max_bytes: 1024 * 1024,
+                max_files: 1,
+            }),
+        );
+        let script = r#"printf '\033[1;32mok\033[0m\n10%%\r50%%\r\033[Kdone\nplain\n'"#;
+        let exit = WaitCondition {
+            exit: true,
+            ..Default::default()
+        };
+        let all = OutputFilter::default();
+        for keep_raw_output in [false, true] {
+            let created = manager
+                .create(CreateAgentRequest {
+                    keep_raw_output,
+                    ..shell_agent(script)
+                })
+                .await
+                .unwrap();
+            let id = &created.agent_id;
+            manager
+                .wait_for_output(id, None, &exit, Duration::from_secs(5))
+                .await
+                .unwrap();
+            let records = manager.read_output(id, 0, 10, &all).await.unwrap().records;
+            let text: Vec<_> = records.iter().map(|r| r.text.as_str()).collect();
+            assert_eq!(text, ["ok", "done", "plain"]);
+            let raw: Vec<_> = records.iter().map(|r| r.raw.as_deref()).collect();
+            if keep_raw_output {
+                assert_eq!(
+                    raw,
+                    [
+                        Some("\x1b[1;32mok\x1b[0m"),
+                        Some("10%\r50%\r\x1b[Kdone"),
+                        None
+                    ]
+                );
+            } else {
+                assert_eq!(raw, [None, None, None]);
+            }
+            assert!(manager
+                .get_buffer(id, &all)
+                .await
+                .unwrap()
+                .starts_with("ok\ndone\n"));
+
+            // The transcript has the raw form either way
+            let page = manager.read_transcript(id, 0, 10, &all).await.unwrap();
+            assert_eq!(page.records[0].raw.as_deref(), Some("\x1b[1;32mok\x1b[0m"));
+            assert_eq!(page.records[2].raw, None);
+            manager.stop(id, StopSignal::Kill, None).await.unwrap();
+        }
+    }
 }
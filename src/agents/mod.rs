@@ -1,3 +1,5 @@
 pub mod manager;
 pub mod model;
 pub mod process;
+pub mod terminal;
+pub mod transcript;
@@ -3,12 +3,116 @@ use std::collections::{HashMap, VecDeque};
 use std::path::PathBuf;
 use time::OffsetDateTime;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct CreateAgentRequest {
     pub name: Option<String>,
     pub working_dir: Option<PathBuf>,
     pub env: HashMap<String, String>,
     pub args: Vec<String>,
+    /// Opt this agent out of the idle reaper (IDLE_REAP_MINS).
+    #[serde(default)]
+    pub idle_reap_exempt: bool,
+    #[serde(default)]
+    pub limits: ResourceLimits,
+    #[serde(default)]
+    pub restart_policy: RestartPolicy,
+    /// Persist the full output to a transcript file. Defaults to on when TRANSCRIPT_DIR is set.
+    #[serde(default)]
+    pub transcript: Option<bool>,
+    /// Run the agent under a pseudo-terminal of this size instead of pipes (unix only).
+    #[serde(default)]
+    pub pty: Option<PtySize>,
+    /// Also keep the raw form of lines rewritten by terminal normalization in the buffer.
+    /// Transcripts always keep it.
+    #[serde(default)]
+    pub keep_raw_output: bool,
+}
+
+/// Window size of an agent's pseudo-terminal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PtySize {
+    pub rows: u16,
+    pub cols: u16,
+}
+
+impl Default for PtySize {
+    fn default() -> Self {
+        Self { rows: 24, cols: 80 }
+    }
+}
+
+/// Which pipe of the child a line of output came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RestartMode {
+    #[default]
+    Never,
+    /// Restart after a non-zero exit or death by signal
+    OnFailure,
+    /// Restart after any exit that was not requested via stop/reset
+    Always,
+}
+
+/// When and how fast to respawn an agent whose process exits on its own.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RestartPolicy {
+    pub mode: RestartMode,
+    /// Consecutive restarts allowed before the agent is flagged as crash-looping and left down
+    pub max_retries: u32,
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        Self {
+            mode: RestartMode::Never,
+            max_retries: 5,
+            initial_backoff_ms: 500,
+            max_backoff_ms: 30_000,
+        }
+    }
+}
+
+impl RestartPolicy {
+    /// Exponential backoff before restart number `attempt` (0-based).
+    pub fn backoff(&self, attempt: u32) -> std::time::Duration {
+        let ms = self
+            .initial_backoff_ms
+            .saturating_mul(1u64 << attempt.min(32))
+            .min(self.max_backoff_ms);
+        std::time::Duration::from_millis(ms)
+    }
+}
+
+/// Per-agent rlimits applied to the child before exec (unix only). Unset fields are inherited.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResourceLimits {
+    /// RLIMIT_CPU: CPU seconds before SIGXCPU/SIGKILL
+    pub cpu_secs: Option<u64>,
+    /// RLIMIT_AS: maximum virtual address space in bytes
+    pub address_space_bytes: Option<u64>,
+    /// RLIMIT_NOFILE: maximum open file descriptors
+    pub open_files: Option<u64>,
+    /// RLIMIT_NPROC: maximum processes for the agent's user
+    pub max_processes: Option<u64>,
+    /// RLIMIT_CORE: maximum core dump size in bytes
+    pub core_bytes: Option<u64>,
+}
+
+impl ResourceLimits {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -23,45 +127,403 @@ pub struct AgentInfo {
     pub name: Option<String>,
     pub pid: u32,
     pub created_at: OffsetDateTime,
+    pub last_used: OffsetDateTime,
+    pub idle_reap_exempt: bool,
+    pub limits: ResourceLimits,
     pub status: String,
+    /// Exit code/signal/time of the most recent process exit, if any
+    pub exit_code: Option<i32>,
+    pub exit_signal: Option<i32>,
+    pub exited_at: Option<OffsetDateTime>,
+    pub restart_policy: RestartPolicy,
+    /// Automatic restarts performed by the restart policy
+    pub restart_count: u32,
+    /// Set when `max_retries` consecutive restarts failed and the agent was left down
+    pub crash_loop: bool,
+    /// Current transcript file, when transcripts are enabled for this agent
+    pub transcript_path: Option<PathBuf>,
+    /// Current window size, for agents running under a pseudo-terminal
+    pub pty: Option<PtySize>,
+}
+
+/// Lifecycle of an agent, driven by the task that waits on its child process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AgentStatus {
+    Starting,
+    Running,
+    Exited(i32),
+    Signaled(i32),
+    Restarting,
+    /// Suspended with SIGSTOP through `signal_agent` until SIGCONT
+    Paused,
+    Stopped,
+}
+
+impl AgentStatus {
+    pub fn label(&self) -> &'static str {
+        match self {
+            AgentStatus::Starting => "starting",
+            AgentStatus::Running => "running",
+            AgentStatus::Exited(_) => "exited",
+            AgentStatus::Signaled(_) => "signaled",
+            AgentStatus::Restarting => "restarting",
+            AgentStatus::Paused => "paused",
+            AgentStatus::Stopped => "stopped",
+        }
+    }
+}
+
+/// Signals `signal_agent` may deliver to an agent's process group.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AgentSignal {
+    #[serde(rename = "SIGINT", alias = "INT")]
+    Int,
+    #[serde(rename = "SIGTERM", alias = "TERM")]
+    Term,
+    #[serde(rename = "SIGHUP", alias = "HUP")]
+    Hup,
+    #[serde(rename = "SIGSTOP", alias = "STOP")]
+    Stop,
+    #[serde(rename = "SIGCONT", alias = "CONT")]
+    Cont,
+    #[serde(rename = "SIGUSR1", alias = "USR1")]
+    Usr1,
+}
+
+impl AgentSignal {
+    pub fn name(&self) -> &'static str {
+        match self {
+            AgentSignal::Int => "SIGINT",
+            AgentSignal::Term => "SIGTERM",
+            AgentSignal::Hup => "SIGHUP",
+            AgentSignal::Stop => "SIGSTOP",
+            AgentSignal::Cont => "SIGCONT",
+            AgentSignal::Usr1 => "SIGUSR1",
+        }
+    }
+}
+
+/// How and when one process generation of an agent ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExitInfo {
+    pub code: Option<i32>,
+    pub signal: Option<i32>,
+    pub at: OffsetDateTime,
+}
+
+impl ExitInfo {
+    pub fn status(&self) -> AgentStatus {
+        match (self.code, self.signal) {
+            (_, Some(sig)) => AgentStatus::Signaled(sig),
+            (Some(code), None) => AgentStatus::Exited(code),
+            // wait() failed; nothing better to report
+            (None, None) => AgentStatus::Exited(-1),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AgentState {
+    pub status: AgentStatus,
+    /// Bumped on every (re)spawn so a stale waiter cannot overwrite a newer process' state
+    pub generation: u64,
+    pub started_at: OffsetDateTime,
+    pub last_exit: Option<ExitInfo>,
+    pub restart_count: u32,
+    /// Restarts since the process last stayed up long enough to count as stable
+    pub consecutive_restarts: u32,
+    pub crash_loop: bool,
+}
+
+/// How `stop` brought the agent down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StopPath {
+    /// Process had already exited before any signal was sent
+    AlreadyExited,
+    /// Exited on SIGTERM within the grace period
+    Terminated,
+    /// Ignored SIGTERM for the grace period and was SIGKILLed
+    Escalated,
+    /// SIGKILLed directly
+    Killed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StopOutcome {
+    pub path: StopPath,
+    pub exit_code: Option<i32>,
+    pub exit_signal: Option<i32>,
+    /// Processes left in the agent's process group after it exited; these were SIGKILLed
+    pub stragglers: Vec<u32>,
+}
+
+/// Something observable about an agent changed; published by the manager for notifications.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AgentEvent {
+    pub agent_id: String,
+    pub kind: AgentEventKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AgentEventKind {
+    Created,
+    /// Stopped and removed from the manager
+    Removed,
+    /// New output was recorded or the buffer was cleared
+    Output,
+    /// Lifecycle status, exit info or restart counters changed
+    Status,
+    /// A new summary was recorded
+    Summary,
+}
+
+/// The most recent summary of an agent's output produced by `get_agent_progress`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgentSummary {
+    pub summary: String,
+    pub backend: String,
+    pub tokens_used: usize,
+    pub instructions: Option<String>,
+    pub created_at: OffsetDateTime,
+}
+
+/// Why and when the idle reaper stopped an agent.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReapRecord {
+    pub agent_id: String,
+    pub name: Option<String>,
+    pub reaped_at: OffsetDateTime,
+    pub idle_secs: u64,
+    pub reason: String,
+}
+
+/// One line of agent output as captured by the pumps. `text` is the line as a terminal would show
+/// it, minus the line terminator: escape sequences stripped and carriage-return overwrites applied.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OutputRecord {
+    /// Monotonic per agent, across evictions, clears and restarts
+    pub seq: u64,
+    #[serde(with = "time::serde::rfc3339")]
+    pub ts: OffsetDateTime,
+    pub stream: OutputStream,
+    pub text: String,
+    /// Exactly what the process wrote, when it differs from `text` and was kept
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub raw: Option<String>,
+}
+
+impl OutputRecord {
+    /// Bytes this record counts against the buffer capacity
+    fn size(&self) -> usize {
+        self.text.len() + self.raw.as_ref().map_or(0, String::len)
+    }
+}
+
+/// Selects output records by stream and time window; empty matches everything.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OutputFilter {
+    pub stream: Option<OutputStream>,
+    /// Inclusive lower bound on the record timestamp (RFC 3339)
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub since: Option<OffsetDateTime>,
+    /// Exclusive upper bound on the record timestamp (RFC 3339)
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub until: Option<OffsetDateTime>,
+}
+
+impl OutputFilter {
+    pub fn matches(&self, rec: &OutputRecord) -> bool {
+        self.stream.is_none_or(|s| s == rec.stream)
+            && self.since.is_none_or(|t| rec.ts >= t)
+            && self.until.is_none_or(|t| rec.ts < t)
+    }
 }
 
 #[derive(Debug)]
 pub struct AgentOutputBuffer {
-    pub lines: VecDeque<String>,
+    pub records: VecDeque<OutputRecord>,
     pub capacity_bytes: usize,
     pub current_bytes: usize,
+    /// Sequence number the next pushed record gets
+    pub next_seq: u64,
+}
+
+/// Result of an incremental read from an output buffer.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutputPage {
+    pub records: Vec<OutputRecord>,
+    /// Pass as `cursor` to the next read to continue where this one stopped
+    pub next_cursor: u64,
+    /// Lines between the requested cursor and the oldest buffered line that were already evicted
+    pub evicted: u64,
+    /// More lines are buffered past `next_cursor`
+    pub has_more: bool,
+}
+
+/// What `wait_for_output` waits for; it returns as soon as any one condition holds.
+#[derive(Debug, Clone, Default)]
+pub struct WaitCondition {
+    /// A new line passing `filter` matches this pattern
+    pub pattern: Option<regex::Regex>,
+    /// No output at all for this long
+    pub idle: Option<std::time::Duration>,
+    /// The process exits (or the agent is stopped)
+    pub exit: bool,
+    /// Lines considered for `pattern`
+    pub filter: OutputFilter,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WaitReason {
+    Matched,
+    Idle,
+    Exited,
+    Timeout,
+    /// A `send_and_expect` step could not write its input
+    SendFailed,
+}
+
+/// Why `wait_for_output` returned and what it saw.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WaitOutcome {
+    pub reason: WaitReason,
+    /// Lines matching the pattern, oldest first
+    pub matches: Vec<OutputRecord>,
+    /// Pass as `cursor` to the next read or wait to continue after the lines examined
+    pub next_cursor: u64,
+    /// Agent status when the wait ended
+    pub status: String,
+    pub waited_ms: u64,
+}
+
+/// One step of a `send_and_expect` script: write `send` (if any), then wait for a line matching
+/// any of `expect`.
+#[derive(Debug, Clone)]
+pub struct ExpectStep {
+    pub send: Option<String>,
+    pub expect: Vec<regex::Regex>,
+    pub timeout: std::time::Duration,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExpectStepResult {
+    /// `matched`, or why the script stopped here (`timeout`, `exited`, `send_failed`)
+    pub reason: WaitReason,
+    /// Index into the step's `expect` of the pattern that matched
+    pub matched: Option<usize>,
+    pub pattern: Option<String>,
+    /// Output from the send up to and including the matching line
+    pub output: Vec<OutputRecord>,
+    pub waited_ms: u64,
+    /// Why the input could not be written, for `send_failed`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Result of a `send_and_expect` script.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExpectOutcome {
+    /// Every step matched
+    pub completed: bool,
+    /// One entry per step that ran; a script stops at its first unmatched step
+    pub steps: Vec<ExpectStepResult>,
+    /// Sequence number after the last line examined
+    pub next_cursor: u64,
+    /// Agent status when the script ended
+    pub status: String,
 }
 
 impl AgentOutputBuffer {
     pub fn new(capacity_bytes: usize) -> Self {
         Self {
-            lines: VecDeque::new(),
+            records: VecDeque::new(),
             capacity_bytes,
             current_bytes: 0,
+            next_seq: 0,
         }
     }
 
-    pub fn push_line(&mut self, line: String) {
-        let added = line.len();
-        if self.current_bytes + added > self.capacity_bytes {
-            self.lines.reserve(1);
-        }
-        self.lines.push_back(line);
-        self.current_bytes += added;
+    /// Sequence number of the oldest buffered record (or of the next one when empty).
+    pub fn first_seq(&self) -> u64 {
+        self.records.front().map_or(self.next_seq, |r| r.seq)
+    }
+
+    /// Drop all buffered records without reusing their sequence numbers.
+    pub fn clear(&mut self) {
+        self.records.clear();
+        self.current_bytes = 0;
+    }
+
+    /// Append a line, stamping it with the next sequence number and the current time, and evict
+    /// the oldest records beyond capacity. A kept `raw` form counts against the capacity too.
+    /// Returns the stored record.
+    pub fn push_line(
+        &mut self,
+        stream: OutputStream,
+        text: String,
+        raw: Option<String>,
+    ) -> OutputRecord {
+        let rec = OutputRecord {
+            seq: self.next_seq,
+            ts: OffsetDateTime::now_utc(),
+            stream,
+            text,
+            raw,
+        };
+        self.next_seq += 1;
+        self.current_bytes += rec.size();
+        self.records.push_back(rec.clone());
         while self.current_bytes > self.capacity_bytes {
-            if let Some(front) = self.lines.pop_front() {
-                self.current_bytes = self.current_bytes.saturating_sub(front.len());
+            if let Some(front) = self.records.pop_front() {
+                self.current_bytes = self.current_bytes.saturating_sub(front.size());
             } else {
                 break;
             }
         }
+        rec
+    }
+
+    /// Up to `limit` records matching `filter` with sequence number >= `cursor`, oldest first.
+    pub fn read_from(&self, cursor: u64, limit: usize, filter: &OutputFilter) -> OutputPage {
+        let first = self.first_seq();
+        let evicted = first.saturating_sub(cursor);
+        let mut records = Vec::new();
+        let start = cursor.max(first).min(self.next_seq);
+        let mut next_cursor = start;
+        let mut has_more = false;
+        for rec in self.records.iter().filter(|r| r.seq >= start) {
+            if !filter.matches(rec) {
+                // Skipped records are consumed so the cursor keeps moving
+                next_cursor = rec.seq + 1;
+                continue;
+            }
+            if records.len() >= limit {
+                has_more = true;
+                break;
+            }
+            next_cursor = rec.seq + 1;
+            records.push(rec.clone());
+        }
+        OutputPage {
+            records,
+            next_cursor,
+            evicted,
+            has_more,
+        }
     }
 
-    pub fn concat(&self) -> String {
+    /// Render matching records as text for summarization, one per line. Stderr lines are labelled
+    /// here only; the stored text is left as written.
+    pub fn concat(&self, filter: &OutputFilter) -> String {
         let mut s = String::with_capacity(self.current_bytes.min(self.capacity_bytes));
-        for l in &self.lines {
-            s.push_str(l);
+        for r in self.records.iter().filter(|r| filter.matches(r)) {
+            if r.stream == OutputStream::Stderr {
+                s.push_str("[stderr] ");
+            }
+            s.push_str(&r.text);
             s.push('\n');
         }
         s
@@ -70,19 +532,23 @@ impl AgentOutputBuffer {
 
 #[cfg(test)]
 mod tests {
-    use super::AgentOutputBuffer;
+    use super::{AgentOutputBuffer, OutputFilter, OutputStream};
+
+    fn out(buf: &mut AgentOutputBuffer, text: &str) {
+        buf.push_line(OutputStream::Stdout, text.to_string(), None);
+    }
 
     #[test]
     fn push_line_trims_when_over_capacity() {
         let mut buf = AgentOutputBuffer::new(10);
-        buf.push_line("12345".to_string());
-        buf.push_line("6789".to_string());
-        let s = buf.concat();
+        out(&mut buf, "12345");
+        out(&mut buf, "6789");
+        let s = buf.concat(&OutputFilter::default());
         assert!(s.contains("12345"));
         assert!(s.contains("6789"));
 
-        buf.push_line("ABCDEFGHIJ".to_string());
-        let s = buf.concat();
+        out(&mut buf, "ABCDEFGHIJ");
+        let s = buf.concat(&OutputFilter::default());
         assert!(s.contains("ABCDEFGHIJ"));
         assert!(s.len() >= "ABCDEFGHIJ\n".len());
     }
@@ -90,10 +556,96 @@ mod tests {
     #[test]
     fn concat_preserves_order_and_trailing_newlines() {
         let mut buf = AgentOutputBuffer::new(100);
-        buf.push_line("first".to_string());
-        buf.push_line("second".to_string());
-        let s = buf.concat();
+        out(&mut buf, "first");
+        out(&mut buf, "second");
+        let s = buf.concat(&OutputFilter::default());
         assert!(s.starts_with("first\nsecond\n"));
         assert!(s.ends_with('\n'));
     }
+
+    #[test]
+    fn read_from_pages_with_monotonic_cursors() {
+        let mut buf = AgentOutputBuffer::new(100);
+        for i in 0..5 {
+            out(&mut buf, &format!("l{i}"));
+        }
+        let all = OutputFilter::default();
+        let page = buf.read_from(1, 2, &all);
+        let seqs: Vec<_> = page.records.iter().map(|r| r.seq).collect();
+        assert_eq!(seqs, vec![1, 2]);
+        assert_eq!(page.records[0].text, "l1");
+        assert_eq!(page.next_cursor, 3);
+        assert!(page.has_more);
+        assert_eq!(page.evicted, 0);
+
+        let page = buf.read_from(page.next_cursor, 10, &all);
+        assert_eq!(page.records.len(), 2);
+        assert_eq!(page.next_cursor, 5);
+        assert!(!page.has_more);
+
+        // Reading at the tail returns nothing and keeps the cursor
+        let page = buf.read_from(5, 10, &all);
+        assert!(page.records.is_empty());
+        assert_eq!(page.next_cursor, 5);
+    }
+
+    #[test]
+    fn read_from_reports_evicted_lines() {
+        let mut buf = AgentOutputBuffer::new(6);
+        for i in 0..5 {
+            out(&mut buf, &format!("l{i}"));
+        }
+        // Only the last three two-byte lines fit
+        assert_eq!(buf.first_seq(), 2);
+        let all = OutputFilter::default();
+        let page = buf.read_from(0, 10, &all);
+        assert_eq!(page.evicted, 2);
+        assert_eq!(page.records[0].seq, 2);
+        assert_eq!(page.next_cursor, 5);
+
+        buf.clear();
+        out(&mut buf, "after");
+        let page = buf.read_from(5, 10, &all);
+        assert_eq!(page.records[0].seq, 5);
+        assert_eq!(page.records[0].text, "after");
+    }
+
+    #[test]
+    fn filters_by_stream_and_time_without_touching_text() {
+        let mut buf = AgentOutputBuffer::new(1024);
+        out(&mut buf, "before");
+        let mark = time::OffsetDateTime::now_utc();
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        buf.push_line(OutputStream::Stderr, "boom".to_string(), None);
+        out(&mut buf, "after");
+
+        let stderr = OutputFilter {
+            stream: Some(OutputStream::Stderr),
+            ..Default::default()
+        };
+        let page = buf.read_from(0, 10, &stderr);
+        assert_eq!(page.records.len(), 1);
+        assert_eq!(page.records[0].text, "boom");
+        // Non-matching records are still consumed by the cursor
+        assert_eq!(page.next_cursor, 3);
+        assert_eq!(buf.concat(&stderr), "[stderr] boom\n");
+
+        let recent = OutputFilter {
+            since: Some(mark),
+            ..Default::default()
+        };
+        let texts: Vec<_> = buf
+            .read_from(0, 10, &recent)
+            .records
+            .into_iter()
+            .map(|r| r.text)
+            .collect();
+        assert_eq!(texts, vec!["boom", "after"]);
+
+        let early = OutputFilter {
+            until: Some(mark),
+            ..Default::default()
+        };
+        assert_eq!(buf.concat(&early), "before\n");
+    }
 }
@@ -1 +1,420 @@
-// Reserved for future process-specific helpers (timeouts, rlimits)
+// Process-specific helpers (process groups, signals, rlimits, pseudo-terminals, exit status decoding)
+use crate::agents::model::{AgentSignal, PtySize, ResourceLimits};
+use crate::errors::AgentError;
+use std::process::ExitStatus;
+
+/// Ask every process in the group to terminate gracefully (SIGTERM). Unsupported on non-unix platforms.
+pub fn request_terminate(pgid: u32) -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        signal_group(pgid, libc::SIGTERM)?;
+        // A paused group only acts on the SIGTERM once it runs again
+        let _ = signal_group(pgid, libc::SIGCONT);
+        Ok(())
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = pgid;
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "graceful termination is not supported on this platform",
+        ))
+    }
+}
+
+/// Deliver `signal` to every process in the group. Unsupported on non-unix platforms.
+pub fn send_signal(pgid: u32, signal: AgentSignal) -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        let signal = match signal {
+            AgentSignal::Int => libc::SIGINT,
+            AgentSignal::Term => libc::SIGTERM,
+            AgentSignal::Hup => libc::SIGHUP,
+            AgentSignal::Stop => libc::SIGSTOP,
+            AgentSignal::Cont => libc::SIGCONT,
+            AgentSignal::Usr1 => libc::SIGUSR1,
+        };
+        signal_group(pgid, signal)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = pgid;
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            format!("{} is not supported on this platform", signal.name()),
+        ))
+    }
+}
+
+/// Best-effort SIGKILL of every process in the group. No-op on non-unix platforms.
+pub fn kill_group(pgid: u32) {
+    #[cfg(unix)]
+    let _ = signal_group(pgid, libc::SIGKILL);
+    #[cfg(not(unix))]
+    let _ = pgid;
+}
+
+/// Put the child in its own process group (pgid == pid) so the whole tree can be signalled.
+pub fn isolate_process_group(cmd: &mut tokio::process::Command) {
+    #[cfg(unix)]
+    cmd.process_group(0);
+    #[cfg(not(unix))]
+    let _ = cmd;
+}
+
+/// Apply `limits` to the child in a pre-exec hook. Limits above the inherited hard limit are
+/// clamped to it, since an unprivileged process cannot raise its hard limit.
+pub fn apply_resource_limits(
+    cmd: &mut tokio::process::Command,
+    limits: &ResourceLimits,
+) -> Result<(), AgentError> {
+    if limits.is_empty() {
+        return Ok(());
+    }
+    #[cfg(unix)]
+    {
+        use rlimit::Resource;
+        let wanted: Vec<(Resource, u64)> = [
+            (Resource::CPU, limits.cpu_secs),
+            (Resource::AS, limits.address_space_bytes),
+            (Resource::NOFILE, limits.open_files),
+            (Resource::NPROC, limits.max_processes),
+            (Resource::CORE, limits.core_bytes),
+        ]
+        .into_iter()
+        .filter_map(|(r, v)| Some((r, v?)))
+        .collect();
+        if let Some((r, _)) = wanted.iter().find(|(r, _)| !r.is_supported()) {
+            return Err(AgentError::InvalidState(format!(
+                "resource limit {} is not supported on this platform",
+                r.as_name()
+            )));
+        }
+        // SAFETY: the hook only calls getrlimit/setrlimit, which are async-signal-safe
+        unsafe {
+            cmd.pre_exec(move || {
+                for (resource, value) in &wanted {
+                    let (_, hard) = resource.get()?;
+                    let value = (*value).min(hard);
+                    resource.set(value, value)?;
+                }
+                Ok(())
+            });
+        }
+        Ok(())
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = cmd;
+        Err(AgentError::InvalidState(
+            "resource limits are only supported on unix".into(),
+        ))
+    }
+}
+
+/// Master side of an agent's pseudo-terminal. Its output and input both go through the master;
+/// dropping it and every `PtyIo` opened on it hangs up the terminal.
+pub struct Pty {
+    #[cfg(unix)]
+    master: std::sync::Arc<tokio::io::unix::AsyncFd<std::fs::File>>,
+}
+
+impl Pty {
+    /// Another handle on the master: reads return what the agent writes to its terminal, writes
+    /// are typed into it.
+    pub fn open(&self) -> std::io::Result<PtyIo> {
+        #[cfg(unix)]
+        return Ok(PtyIo {
+            master: self.master.clone(),
+        });
+        #[cfg(not(unix))]
+        Err(std::io::ErrorKind::Unsupported.into())
+    }
+
+    /// Change the window size; the kernel sends SIGWINCH to the terminal's foreground group.
+    pub fn resize(&self, size: PtySize) -> std::io::Result<()> {
+        #[cfg(unix)]
+        {
+            use std::os::fd::AsRawFd;
+            let ws = winsize(size);
+            // SAFETY: TIOCSWINSZ only reads the winsize we pass
+            if unsafe { libc::ioctl(self.master.as_raw_fd(), libc::TIOCSWINSZ, &ws) } < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = size;
+            Err(std::io::ErrorKind::Unsupported.into())
+        }
+    }
+}
+
+/// Non-blocking handle on a pty master, driven by the runtime's reactor rather than the blocking
+/// pool, so an idle terminal costs no thread.
+pub struct PtyIo {
+    #[cfg(unix)]
+    master: std::sync::Arc<tokio::io::unix::AsyncFd<std::fs::File>>,
+    #[cfg(not(unix))]
+    never: std::convert::Infallible,
+}
+
+#[cfg(unix)]
+impl PtyIo {
+    fn poll_io<T>(
+        &self,
+        cx: &mut std::task::Context<'_>,
+        interest: tokio::io::Interest,
+        mut op: impl FnMut(&std::fs::File) -> std::io::Result<T>,
+    ) -> std::task::Poll<std::io::Result<T>> {
+        use std::task::Poll;
+        loop {
+            let ready = if interest.is_readable() {
+                self.master.poll_read_ready(cx)
+            } else {
+                self.master.poll_write_ready(cx)
+            };
+            let mut guard = match ready {
+                Poll::Ready(guard) => guard?,
+                Poll::Pending => return Poll::Pending,
+            };
+            match guard.try_io(|master| op(master.get_ref())) {
+                Ok(result) => return Poll::Ready(result),
+                // Readiness was stale; the guard has cleared it, so wait for the next event
+                Err(_would_block) => continue,
+            }
+        }
+    }
+}
+
+impl tokio::io::AsyncRead for PtyIo {
+    fn poll_read(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        #[cfg(unix)]
+        {
+            use std::io::Read;
+            let unfilled = buf.initialize_unfilled();
+            let n = std::task::ready!(self.poll_io(cx, tokio::io::Interest::READABLE, |mut m| {
+                m.read(unfilled)
+            }))?;
+            buf.advance(n);
+            std::task::Poll::Ready(Ok(()))
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = (cx, buf);
+            match self.never {}
+        }
+    }
+}
+
+impl tokio::io::AsyncWrite for PtyIo {
+    fn poll_write(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &[u8],
+    ) -> std::task::Poll<std::io::Result<usize>> {
+        #[cfg(unix)]
+        {
+            use std::io::Write;
+            self.poll_io(cx, tokio::io::Interest::WRITABLE, |mut m| m.write(buf))
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = (cx, buf);
+            match self.never {}
+        }
+    }
+
+    fn poll_flush(
+        self: std::pin::Pin<&mut Self>,
+        _cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        std::task::Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(
+        self: std::pin::Pin<&mut Self>,
+        _cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        std::task::Poll::Ready(Ok(()))
+    }
+}
+
+/// Open a pseudo-terminal of `size` and give its slave side to the child as stdin, stdout, stderr
+/// and controlling terminal. The child starts its own session, which also makes it the leader of
+/// its own process group, so do not combine this with `isolate_process_group`.
+pub fn attach_pty(cmd: &mut tokio::process::Command, size: PtySize) -> Result<Pty, AgentError> {
+    #[cfg(unix)]
+    {
+        use std::os::fd::AsRawFd;
+        use std::os::unix::fs::OpenOptionsExt;
+        let open_err = |e: std::io::Error| AgentError::Io(format!("open pty: {e}"));
+        let last_err = || open_err(std::io::Error::last_os_error());
+        // std opens every file close-on-exec, so neither end can leak into agents spawned while
+        // this one is being set up; the child gets the slave through its stdio
+        let master = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NOCTTY | libc::O_NONBLOCK)
+            .open("/dev/ptmx")
+            .map_err(open_err)?;
+        // SAFETY: grantpt and unlockpt only act on the master we own
+        if unsafe { libc::grantpt(master.as_raw_fd()) } < 0
+            || unsafe { libc::unlockpt(master.as_raw_fd()) } < 0
+        {
+            return Err(last_err());
+        }
+        let slave = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NOCTTY)
+            .open(slave_path(&master).map_err(open_err)?)
+            .map_err(open_err)?;
+        let ws = winsize(size);
+        // SAFETY: TIOCSWINSZ only reads the winsize we pass
+        if unsafe { libc::ioctl(master.as_raw_fd(), libc::TIOCSWINSZ, &ws) } < 0 {
+            return Err(last_err());
+        }
+        let master = tokio::io::unix::AsyncFd::new(master).map_err(open_err)?;
+        cmd.stdin(slave.try_clone().map_err(open_err)?)
+            .stdout(slave.try_clone().map_err(open_err)?)
+            .stderr(slave);
+        // SAFETY: the hook only calls setsid and ioctl, which are async-signal-safe
+        unsafe {
+            cmd.pre_exec(|| {
+                if libc::setsid() < 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                // stdin is the slave by now; make it the new session's controlling terminal
+                if libc::ioctl(0, libc::TIOCSCTTY, 0) < 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        Ok(Pty {
+            master: std::sync::Arc::new(master),
+        })
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (cmd, size);
+        Err(AgentError::InvalidState(
+            "pty agents are only supported on unix".into(),
+        ))
+    }
+}
+
+// Path of the slave device that pairs with `master`
+#[cfg(target_os = "linux")]
+fn slave_path(master: &std::fs::File) -> std::io::Result<std::path::PathBuf> {
+    use std::os::fd::AsRawFd;
+    use std::os::unix::ffi::OsStrExt;
+    let mut buf = [0 as libc::c_char; 128];
+    // SAFETY: ptsname_r writes a NUL-terminated name of at most buf.len() bytes
+    let rc = unsafe { libc::ptsname_r(master.as_raw_fd(), buf.as_mut_ptr(), buf.len()) };
+    if rc != 0 {
+        return Err(std::io::Error::from_raw_os_error(rc));
+    }
+    // SAFETY: on success the buffer holds a NUL-terminated string
+    let name = unsafe { std::ffi::CStr::from_ptr(buf.as_ptr()) };
+    Ok(std::ffi::OsStr::from_bytes(name.to_bytes()).into())
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn slave_path(master: &std::fs::File) -> std::io::Result<std::path::PathBuf> {
+    use std::os::fd::AsRawFd;
+    use std::os::unix::ffi::OsStrExt;
+    // ptsname returns a static buffer; serialize callers so the name is copied out intact
+    static PTSNAME: std::sync::Mutex<()> = std::sync::Mutex::new(());
+    let _guard = PTSNAME.lock().unwrap_or_else(|e| e.into_inner());
+    // SAFETY: a non-null result points to a NUL-terminated string valid until the next call
+    let name = unsafe { libc::ptsname(master.as_raw_fd()) };
+    if name.is_null() {
+        return Err(std::io::Error::last_os_error());
+    }
+    // SAFETY: checked non-null above, and the lock keeps the buffer from changing
+    let name = unsafe { std::ffi::CStr::from_ptr(name) };
+    Ok(std::ffi::OsStr::from_bytes(name.to_bytes()).into())
+}
+
+#[cfg(unix)]
+fn winsize(size: PtySize) -> libc::winsize {
+    libc::winsize {
+        ws_row: size.rows,
+        ws_col: size.cols,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    }
+}
+
+/// Signal every process in the group led by `pgid`.
+#[cfg(unix)]
+pub fn signal_group(pgid: u32, signal: i32) -> std::io::Result<()> {
+    // SAFETY: killpg(2) has no memory-safety preconditions
+    let rc = unsafe { libc::killpg(pgid as libc::pid_t, signal) };
+    if rc == 0 {
+        Ok(())
+    } else {
+        Err(std::io::Error::last_os_error())
+    }
+}
+
+/// Live (non-zombie) processes still in the group led by `pgid`.
+#[cfg(target_os = "linux")]
+pub fn group_members(pgid: u32) -> Vec<u32> {
+    let Ok(entries) = std::fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(|e| e.ok()?.file_name().to_str()?.parse::<u32>().ok())
+        .filter(|pid| {
+            let Ok(stat) = std::fs::read_to_string(format!("/proc/{pid}/stat")) else {
+                return false;
+            };
+            // Fields after the parenthesised command name: state ppid pgrp ...
+            let Some((_, rest)) = stat.rsplit_once(')') else {
+                return false;
+            };
+            let mut fields = rest.split_whitespace();
+            let state = fields.next();
+            let pgrp = fields.nth(1).and_then(|f| f.parse::<u32>().ok());
+            state != Some("Z") && pgrp == Some(pgid)
+        })
+        .collect()
+}
+
+/// Members cannot be enumerated portably here; report the group id while any member is alive.
+#[cfg(all(unix, not(target_os = "linux")))]
+pub fn group_members(pgid: u32) -> Vec<u32> {
+    // SAFETY: killpg(2) with signal 0 only checks for existence
+    let alive = unsafe { libc::killpg(pgid as libc::pid_t, 0) } == 0;
+    if alive {
+        vec![pgid]
+    } else {
+        Vec::new()
+    }
+}
+
+#[cfg(not(unix))]
+pub fn group_members(_pgid: u32) -> Vec<u32> {
+    Vec::new()
+}
+
+/// Split an exit status into (exit code, terminating signal).
+pub fn exit_details(status: &ExitStatus) -> (Option<i32>, Option<i32>) {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        (status.code(), status.signal())
+    }
+    #[cfg(not(unix))]
+    {
+        (status.code(), None)
+    }
+}
@@ -0,0 +1,174 @@
+// Normalization of terminal output: what a line looks like once a terminal has rendered it.
+// Colors, titles and other escape sequences are dropped, and carriage returns, backspaces and
+// line erases are applied so a spinner or progress bar collapses to its final state.
+use std::borrow::Cow;
+
+const ESC: char = '\x1b';
+const BEL: char = '\x07';
+// Farthest column cursor movement can reach beyond the text already on the row. Output is
+// untrusted, so a huge parameter must not make the row allocate without bound.
+const MAX_COLUMN: usize = 4096;
+
+/// The rendered form of one line of output, borrowed when the line needs no rendering.
+pub fn normalize_line(raw: &str) -> Cow<'_, str> {
+    if !raw.chars().any(|c| c.is_control() && c != '\t') {
+        return Cow::Borrowed(raw);
+    }
+    let mut line = Line::default();
+    let mut chars = raw.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            ESC => match chars.next() {
+                Some('[') => {
+                    // CSI: parameter bytes, intermediate bytes, then one final byte
+                    let mut params = String::new();
+                    while let Some(&p) = chars.peek() {
+                        if !('\x20'..='\x3f').contains(&p) {
+                            break;
+                        }
+                        params.push(p);
+                        chars.next();
+                    }
+                    // A malformed sequence, or one cut off by the end of the line, is dropped
+                    if let Some(f) = chars.next().filter(|f| ('\x40'..='\x7e').contains(f)) {
+                        line.csi(&params, f);
+                    }
+                }
+                Some(']') => {
+                    // OSC (window titles, hyperlinks): runs to BEL or ST (ESC \)
+                    while let Some(o) = chars.next() {
+                        if o == BEL || (o == ESC && chars.next_if_eq(&'\\').is_some()) {
+                            break;
+                        }
+                    }
+                }
+                // Other escapes: optional intermediate bytes, then one final byte
+                Some(i) if ('\x20'..='\x2f').contains(&i) => {
+                    while chars.next_if(|n| ('\x20'..='\x2f').contains(n)).is_some() {}
+                    chars.next();
+                }
+                _ => {}
+            },
+            '\r' => line.cursor = 0,
+            '\x08' => line.cursor = line.cursor.saturating_sub(1),
+            '\t' => line.put('\t'),
+            c if c.is_control() => {}
+            c => line.put(c),
+        }
+    }
+    Cow::Owned(line.render())
+}
+
+// One terminal row; `None` cells are blank, e.g. erased or skipped over by cursor movement
+#[derive(Default)]
+struct Line {
+    cells: Vec<Option<char>>,
+    cursor: usize,
+}
+
+impl Line {
+    fn put(&mut self, c: char) {
+        if self.cursor >= self.cells.len() {
+            self.cells.resize(self.cursor + 1, None);
+        }
+        self.cells[self.cursor] = Some(c);
+        self.cursor += 1;
+    }
+
+    // Apply the CSI sequences that change what the row shows; the rest (colors, cursor movement
+    // between rows, modes) do not affect its text
+    fn csi(&mut self, params: &str, action: char) {
+        let n = match params.parse::<usize>() {
+            Ok(n) => Some(n),
+            Err(e) if *e.kind() == std::num::IntErrorKind::PosOverflow => Some(usize::MAX),
+            Err(_) => None,
+        };
+        match action {
+            // Erase in line: to the end, to the start, or all of it
+            'K' => match n.unwrap_or(0) {
+                0 => self.cells.truncate(self.cursor),
+                1 => {
+                    let end = (self.cursor + 1).min(self.cells.len());
+                    self.cells[..end].fill(None);
+                }
+                2 => self.cells.clear(),
+                _ => {}
+            },
+            // Cursor forward, back, and to an absolute column (1-based)
+            'C' => self.cursor = self.cursor.saturating_add(n.unwrap_or(1).max(1)),
+            'D' => self.cursor = self.cursor.saturating_sub(n.unwrap_or(1).max(1)),
+            'G' => self.cursor = n.unwrap_or(1).saturating_sub(1),
+            _ => {}
+        }
+        self.cursor = self.cursor.min(self.cells.len().max(MAX_COLUMN));
+    }
+
+    fn render(mut self) -> String {
+        while self.cells.last() == Some(&None) {
+            self.cells.pop();
+        }
+        self.cells.into_iter().map(|c| c.unwrap_or(' ')).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_lines_are_borrowed() {
+        assert!(matches!(normalize_line("hello\tworld"), Cow::Borrowed(_)));
+        assert!(matches!(normalize_line(""), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn escape_sequences_are_stripped() {
+        assert_eq!(normalize_line("\x1b[1;32mok\x1b[0m done"), "ok done");
+        assert_eq!(normalize_line("\x1b]0;my title\x07prompt$ "), "prompt$ ");
+        assert_eq!(
+            normalize_line("\x1b]8;;https://example.com\x1b\\link\x1b]8;;\x1b\\"),
+            "link"
+        );
+        assert_eq!(normalize_line("\x1b(Bcharset\x1b=keypad"), "charsetkeypad");
+        assert_eq!(
+            normalize_line("\x1b[?25lhidden cursor\x1b[?25h"),
+            "hidden cursor"
+        );
+        // Sequences cut off by the end of the line vanish
+        assert_eq!(normalize_line("tail\x1b[3"), "tail");
+        assert_eq!(normalize_line("bell\x07"), "bell");
+    }
+
+    #[test]
+    fn carriage_returns_collapse_to_the_final_state() {
+        assert_eq!(normalize_line("10%\r20%\r100%"), "100%");
+        assert_eq!(normalize_line("loading...\rdone"), "doneing...");
+        assert_eq!(normalize_line("loading...\r\x1b[Kdone"), "done");
+        assert_eq!(
+            normalize_line("\x1b[2K\r⠋ working\r\x1b[2K\r✔ built"),
+            "✔ built"
+        );
+        assert_eq!(normalize_line("windows line\r"), "windows line");
+        assert_eq!(normalize_line("abc\x08\x08X"), "aXc");
+        assert_eq!(normalize_line("abcdef\x1b[3G\x1b[1K"), "   def");
+        assert_eq!(normalize_line("a\x1b[3Cb"), "a   b");
+    }
+
+    #[test]
+    fn cursor_movement_is_bounded() {
+        let far = normalize_line("\x1b[3000000000Gx");
+        assert_eq!(far.len(), MAX_COLUMN + 1);
+        assert!(far.ends_with('x'));
+        let far = normalize_line("a\x1b[18446744073709551615Cx");
+        assert_eq!(far.len(), MAX_COLUMN + 1);
+        assert!(far.starts_with('a') && far.ends_with('x'));
+        // Parameters too large for usize saturate instead of falling back to the default of 1
+        let far = normalize_line("\x1b[99999999999999999999999Cy");
+        assert_eq!(far.len(), MAX_COLUMN + 1);
+        // Long lines keep all their text; only movement past it is bounded
+        let long = format!("{}\x1b[99999C!", "z".repeat(MAX_COLUMN * 2));
+        let long = normalize_line(&long);
+        assert_eq!(long.len(), MAX_COLUMN * 2 + 1);
+        assert!(long.ends_with("z!"));
+    }
+}
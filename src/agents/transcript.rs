@@ -0,0 +1,263 @@
+// Full per-agent transcripts persisted as JSONL, with size-based rotation.
+// Files live in the configured directory as `<agent_id>.jsonl` (current) and
+// `<agent_id>.<k>.jsonl` (rotated, k = 1 is the most recent).
+use crate::agents::model::{OutputFilter, OutputRecord};
+use crate::errors::AgentError;
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone)]
+pub struct TranscriptConfig {
+    pub dir: PathBuf,
+    /// Rotate the current file once it would grow beyond this many bytes
+    pub max_bytes: u64,
+    /// Rotated files to keep; older ones are deleted
+    pub max_files: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TranscriptPage {
+    pub agent_id: String,
+    pub records: Vec<OutputRecord>,
+    /// Pass as `from` to continue reading
+    pub next: u64,
+    /// Oldest sequence number still on disk
+    pub first_available: Option<u64>,
+    /// True when lines before `from` were requested but have been rotated out
+    pub truncated: bool,
+    pub has_more: bool,
+}
+
+pub struct TranscriptWriter {
+    config: TranscriptConfig,
+    agent_id: String,
+    file: Option<BufWriter<File>>,
+    bytes: u64,
+}
+
+impl TranscriptWriter {
+    pub fn create(config: TranscriptConfig, agent_id: &str) -> Result<Self, AgentError> {
+        std::fs::create_dir_all(&config.dir)
+            .map_err(|e| AgentError::Io(format!("create transcript dir: {e}")))?;
+        let mut w = Self {
+            config,
+            agent_id: agent_id.to_string(),
+            file: None,
+            bytes: 0,
+        };
+        w.open_current()
+            .map_err(|e| AgentError::Io(format!("open transcript: {e}")))?;
+        Ok(w)
+    }
+
+    pub fn path(&self) -> PathBuf {
+        file_path(&self.config.dir, &self.agent_id, 0)
+    }
+
+    /// Append `records` in order. Blocks on file IO; keep it off the async runtime.
+    pub fn append(&mut self, records: &[OutputRecord]) -> std::io::Result<()> {
+        for rec in records {
+            let mut s = serde_json::to_string(rec)?;
+            s.push('\n');
+            if self.bytes > 0 && self.bytes + s.len() as u64 > self.config.max_bytes {
+                self.rotate()?;
+            }
+            let file = match self.file.as_mut() {
+                Some(f) => f,
+                None => self.open_current()?,
+            };
+            file.write_all(s.as_bytes())?;
+            self.bytes += s.len() as u64;
+        }
+        // Flush per batch so readers see everything appended once this returns
+        if let Some(file) = self.file.as_mut() {
+            file.flush()?;
+        }
+        Ok(())
+    }
+
+    fn open_current(&mut self) -> std::io::Result<&mut BufWriter<File>> {
+        let f = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.path())?;
+        self.bytes = f.metadata()?.len();
+        Ok(self.file.insert(BufWriter::new(f)))
+    }
+
+    fn rotate(&mut self) -> std::io::Result<()> {
+        self.file = None;
+        let dir = &self.config.dir;
+        let keep = self.config.max_files;
+        if keep == 0 {
+            std::fs::remove_file(self.path())?;
+        } else {
+            let oldest = file_path(dir, &self.agent_id, keep);
+            if oldest.exists() {
+                std::fs::remove_file(&oldest)?;
+            }
+            for k in (1..keep).rev() {
+                let from = file_path(dir, &self.agent_id, k);
+                if from.exists() {
+                    std::fs::rename(&from, file_path(dir, &self.agent_id, k + 1))?;
+                }
+            }
+            std::fs::rename(self.path(), file_path(dir, &self.agent_id, 1))?;
+        }
+        self.open_current()?;
+        Ok(())
+    }
+}
+
+fn file_path(dir: &Path, agent_id: &str, rotation: usize) -> PathBuf {
+    if rotation == 0 {
+        dir.join(format!("{agent_id}.jsonl"))
+    } else {
+        dir.join(format!("{agent_id}.{rotation}.jsonl"))
+    }
+}
+
+/// Read up to `limit` records matching `filter` with sequence number >= `from`, oldest first,
+/// across rotated files.
+pub fn read_range(
+    config: &TranscriptConfig,
+    agent_id: &str,
+    from: u64,
+    limit: usize,
+    filter: &OutputFilter,
+) -> Result<TranscriptPage, AgentError> {
+    let files: Vec<PathBuf> = (0..=config.max_files)
+        .rev()
+        .map(|k| file_path(&config.dir, agent_id, k))
+        .filter(|p| p.exists())
+        .collect();
+    if files.is_empty() {
+        return Err(AgentError::NotFound(format!("transcript for {agent_id}")));
+    }
+    let mut page = TranscriptPage {
+        agent_id: agent_id.to_string(),
+        records: Vec::new(),
+        next: from,
+        first_available: None,
+        truncated: false,
+        has_more: false,
+    };
+    'files: for path in files {
+        let f = File::open(&path).map_err(|e| AgentError::Io(format!("open transcript: {e}")))?;
+        for line in BufReader::new(f).lines() {
+            let line = line.map_err(|e| AgentError::Io(format!("read transcript: {e}")))?;
+            // Skip a partially written trailing line
+            let Ok(rec) = serde_json::from_str::<OutputRecord>(&line) else {
+                continue;
+            };
+            page.first_available.get_or_insert(rec.seq);
+            if rec.seq < from {
+                continue;
+            }
+            if !filter.matches(&rec) {
+                page.next = rec.seq + 1;
+                continue;
+            }
+            if page.records.len() >= limit {
+                page.has_more = true;
+                break 'files;
+            }
+            page.next = rec.seq + 1;
+            page.records.push(rec);
+        }
+    }
+    page.truncated = page.first_available.is_some_and(|first| from < first);
+    Ok(page)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agents::model::{AgentOutputBuffer, OutputStream};
+
+    fn config(dir: &Path, max_bytes: u64, max_files: usize) -> TranscriptConfig {
+        TranscriptConfig {
+            dir: dir.to_path_buf(),
+            max_bytes,
+            max_files,
+        }
+    }
+
+    // Push through a buffer so records carry real sequence numbers
+    fn write(
+        w: &mut TranscriptWriter,
+        buf: &mut AgentOutputBuffer,
+        stream: OutputStream,
+        text: &str,
+    ) {
+        let rec = buf.push_line(stream, text.to_string(), None);
+        w.append(&[rec]).unwrap();
+    }
+
+    #[test]
+    fn appends_and_reads_ranges() {
+        let tmp = tempfile::tempdir().unwrap();
+        let cfg = config(tmp.path(), 1024 * 1024, 2);
+        let mut w = TranscriptWriter::create(cfg.clone(), "a1").unwrap();
+        let mut buf = AgentOutputBuffer::new(1024);
+        for i in 0..5 {
+            write(&mut w, &mut buf, OutputStream::Stdout, &format!("line {i}"));
+        }
+        write(&mut w, &mut buf, OutputStream::Stderr, "oops");
+        let all = OutputFilter::default();
+
+        let page = read_range(&cfg, "a1", 2, 3, &all).unwrap();
+        let lines: Vec<_> = page.records.iter().map(|r| r.text.as_str()).collect();
+        assert_eq!(lines, vec!["line 2", "line 3", "line 4"]);
+        assert_eq!(page.next, 5);
+        assert!(page.has_more);
+        assert!(!page.truncated);
+
+        let rest = read_range(&cfg, "a1", page.next, 10, &all).unwrap();
+        assert_eq!(rest.records.len(), 1);
+        assert_eq!(rest.records[0].stream, OutputStream::Stderr);
+        assert!(!rest.has_more);
+
+        let stderr = OutputFilter {
+            stream: Some(OutputStream::Stderr),
+            ..Default::default()
+        };
+        let only_err = read_range(&cfg, "a1", 0, 10, &stderr).unwrap();
+        assert_eq!(only_err.records.len(), 1);
+        assert_eq!(only_err.records[0].text, "oops");
+        assert_eq!(only_err.next, 6);
+    }
+
+    #[test]
+    fn rotates_by_size_and_drops_oldest_files() {
+        let tmp = tempfile::tempdir().unwrap();
+        // Each record is ~90 bytes; rotate roughly every two lines and keep one old file
+        let cfg = config(tmp.path(), 200, 1);
+        let mut w = TranscriptWriter::create(cfg.clone(), "a2").unwrap();
+        let mut buf = AgentOutputBuffer::new(1024);
+        for i in 0..10 {
+            write(&mut w, &mut buf, OutputStream::Stdout, &format!("line {i}"));
+        }
+        assert!(file_path(tmp.path(), "a2", 1).exists());
+        assert!(!file_path(tmp.path(), "a2", 2).exists());
+
+        let page = read_range(&cfg, "a2", 0, 100, &OutputFilter::default()).unwrap();
+        assert!(page.truncated);
+        let first = page.first_available.unwrap();
+        assert!(first > 0);
+        assert_eq!(page.records.first().unwrap().seq, first);
+        assert_eq!(page.records.last().unwrap().text, "line 9");
+    }
+
+    #[test]
+    fn missing_transcript_is_not_found() {
+        let tmp = tempfile::tempdir().unwrap();
+        let cfg = config(tmp.path(), 1024, 1);
+        assert!(matches!(
+            read_range(&cfg, "nope", 0, 10, &OutputFilter::default()),
+            Err(AgentError::NotFound(_))
+        ));
+    }
+}
@@ -1,4 +1,15 @@
-use clap::Parser;
+use clap::{Parser, ValueEnum};
+use std::net::SocketAddr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Transport {
+    /// One client: the process that spawned the server, over stdin/stdout
+    Stdio,
+    /// MCP streamable HTTP on `--listen`; any number of local clients share the agents
+    Http,
+    /// Long-lived daemon on the unix socket at `--socket`; each connection is a separate client
+    Unix,
+}
 
 #[derive(Debug, Clone, Parser)]
 #[command(name = "cursor-mcp-subagents")]
@@ -21,6 +32,50 @@ pub struct AppConfig {
 
     #[arg(long, env = "IDLE_REAP_MINS")]
     pub idle_reap_mins: Option<u32>,
+
+    /// Milliseconds an agent gets to exit after SIGTERM before it is SIGKILLed
+    #[arg(long, env = "STOP_GRACE_MS", default_value_t = 5000)]
+    pub stop_grace_ms: u64,
+
+    /// Directory for per-agent JSONL transcripts of the full output. Unset disables transcripts.
+    #[arg(long, env = "TRANSCRIPT_DIR")]
+    pub transcript_dir: Option<std::path::PathBuf>,
+
+    /// Rotate an agent's transcript file once it reaches this size
+    #[arg(long, env = "TRANSCRIPT_MAX_BYTES", default_value_t = 8 * 1024 * 1024)]
+    pub transcript_max_bytes: u64,
+
+    /// Rotated transcript files kept per agent
+    #[arg(long, env = "TRANSCRIPT_MAX_FILES", default_value_t = 4)]
+    pub transcript_max_files: usize,
+
+    /// Milliseconds over which agent output/status changes are coalesced into one resource notification
+    #[arg(long, env = "NOTIFY_DEBOUNCE_MS", default_value_t = 250)]
+    pub notify_debounce_ms: u64,
+
+    /// How clients reach the server
+    #[arg(long, env = "MCP_TRANSPORT", value_enum, default_value_t = Transport::Stdio)]
+    pub transport: Transport,
+
+    /// Address the http transport listens on
+    #[arg(long, env = "MCP_LISTEN", default_value = "127.0.0.1:8848")]
+    pub listen: SocketAddr,
+
+    /// Minutes an http session may go without requests or an open stream before it is dropped; 0 keeps sessions until deleted
+    #[arg(long, env = "HTTP_SESSION_IDLE_MINS", default_value_t = 30)]
+    pub http_session_idle_mins: u32,
+
+    /// Socket path the unix transport listens on
+    #[arg(long, env = "MCP_SOCKET")]
+    pub socket: Option<std::path::PathBuf>,
+
+    /// Maximum number of MCP requests handled concurrently
+    #[arg(long, env = "MAX_CONCURRENT_REQUESTS", default_value_t = 16)]
+    pub max_concurrent_requests: usize,
+
+    /// Largest incoming JSON-RPC message (or batch) in bytes; bigger ones are rejected
+    #[arg(long, env = "MAX_MESSAGE_BYTES", default_value_t = 4 * 1024 * 1024)]
+    pub max_message_bytes: usize,
 }
 
 impl AppConfig {
@@ -35,6 +90,18 @@ impl AppConfig {
         if self.buffer_bytes > 100 * 1024 * 1024 {
             return Err("buffer_bytes too large (max 100MB)".into());
         }
+        if self.max_concurrent_requests == 0 {
+            return Err("max_concurrent_requests must be > 0".into());
+        }
+        if self.max_message_bytes == 0 {
+            return Err("max_message_bytes must be > 0".into());
+        }
+        if self.transport == Transport::Unix && self.socket.is_none() {
+            return Err("--socket is required with --transport unix".into());
+        }
+        if self.transcript_dir.is_some() && self.transcript_max_bytes == 0 {
+            return Err("transcript_max_bytes must be > 0".into());
+        }
         if self.summary_backend == "ollama" {
             url::Url::parse(&self.ollama_host)
                 .map_err(|_| "Invalid OLLAMA_HOST URL format".to_string())?;
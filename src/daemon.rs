@@ -0,0 +1,185 @@
+// Long-lived daemon mode: clients connect over a unix socket and speak the same framing as stdio.
+// Every connection is its own MCP session (initialization, subscriptions, framing) over the shared
+// agent manager, so agents outlive the client that created them.
+use crate::mcp::McpServer;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use tokio::net::UnixListener;
+
+/// Accept clients on the socket at `path` until accepting fails. A stale socket file left by a
+/// previous run is replaced; a live one is an error.
+pub async fn serve_unix(server: McpServer, path: &Path) -> anyhow::Result<()> {
+    if path.exists() {
+        if std::os::unix::net::UnixStream::connect(path).is_ok() {
+            anyhow::bail!("another server is already listening on {}", path.display());
+        }
+        std::fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    // Clients get full control of the host's agents; only the owner may connect
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    tracing::info!(socket=%path.display(), "serving MCP on unix socket");
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let session = server.new_session();
+        tokio::spawn(async move {
+            tracing::info!("client connected");
+            // The framing helpers are blocking; hand the stream to them as a std socket
+            let stream = match stream
+                .into_std()
+                .and_then(|s| s.set_nonblocking(false).map(|_| s))
+            {
+                Ok(s) => s,
+                Err(e) => {
+                    tracing::warn!(error=?e, "failed to set up client connection");
+                    return;
+                }
+            };
+            let reader = match stream.try_clone() {
+                Ok(r) => r,
+                Err(e) => {
+                    tracing::warn!(error=?e, "failed to set up client connection");
+                    return;
+                }
+            };
+            session.serve_stream(reader, stream).await;
+            tracing::info!("client disconnected");
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agents::manager::AgentManagerImpl;
+    use crate::errors::SummarizeError;
+    use crate::summarize::{SummarizeResult, Summarizer};
+    use serde_json::{json, Value};
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::os::unix::net::UnixStream;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    struct NoSummarizer;
+    impl Summarizer for NoSummarizer {
+        fn summarize(
+            &self,
+            _context: &str,
+            _instructions: Option<&str>,
+            _max_tokens: usize,
+        ) -> Result<SummarizeResult, SummarizeError> {
+            Err(SummarizeError::Unavailable)
+        }
+    }
+
+    fn connect(path: &Path) -> UnixStream {
+        for _ in 0..100 {
+            if let Ok(s) = UnixStream::connect(path) {
+                s.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+                return s;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        panic!("daemon did not start");
+    }
+
+    fn initialize() -> Value {
+        json!({"jsonrpc":"2.0","id": 0,"method":"initialize","params":{"protocolVersion":"2025-06-18"}})
+    }
+
+    fn call(id: u64, name: &str, arguments: Value) -> Value {
+        json!({"jsonrpc":"2.0","id": id,"method":"tools/call","params":{"name": name,"arguments": arguments}})
+    }
+
+    fn send_framed(s: &mut UnixStream, v: &Value) {
+        let body = v.to_string();
+        write!(s, "Content-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+    }
+
+    fn read_framed(r: &mut BufReader<UnixStream>) -> Value {
+        let mut len = None;
+        loop {
+            let mut line = String::new();
+            r.read_line(&mut line).unwrap();
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            if let Some(v) = line.strip_prefix("Content-Length:") {
+                len = Some(v.trim().parse::<usize>().unwrap());
+            }
+        }
+        let mut body = vec![0u8; len.expect("Content-Length")];
+        r.read_exact(&mut body).unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    fn read_line_json(r: &mut BufReader<UnixStream>) -> Value {
+        let mut line = String::new();
+        r.read_line(&mut line).unwrap();
+        serde_json::from_str(&line).unwrap()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn clients_share_agents_with_their_own_framing() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("mcp.sock");
+        let manager = Arc::new(AgentManagerImpl::new(Some("/bin/cat".into()), 1024));
+        let server = McpServer::new(manager.clone(), Arc::new(NoSummarizer));
+        let daemon = tokio::spawn({
+            let path = path.clone();
+            async move { serve_unix(server, &path).await }
+        });
+
+        let client_path = path.clone();
+        let agent_id = tokio::task::spawn_blocking(move || {
+            // First client: Content-Length framing
+            let mut framed = connect(&client_path);
+            let mut framed_in = BufReader::new(framed.try_clone().unwrap());
+            send_framed(&mut framed, &initialize());
+            assert!(read_framed(&mut framed_in)["result"].is_object());
+            send_framed(&mut framed, &call(1, "create_agent", json!({})));
+            let resp = read_framed(&mut framed_in);
+            let agent_id = resp["result"]["structuredContent"]["agent_id"]
+                .as_str()
+                .unwrap()
+                .to_string();
+
+            // Second client: ND-JSON, answered in kind while the first keeps its framing
+            let mut raw = connect(&client_path);
+            let mut raw_in = BufReader::new(raw.try_clone().unwrap());
+            writeln!(raw, "{}", initialize()).unwrap();
+            assert!(read_line_json(&mut raw_in)["result"].is_object());
+            writeln!(raw, "{}", call(1, "list_agents", json!({}))).unwrap();
+            let resp = read_line_json(&mut raw_in);
+            let agents = resp["result"]["structuredContent"]["agents"].clone();
+            assert_eq!(agents[0]["agent_id"], agent_id.as_str());
+            send_framed(&mut framed, &call(2, "list_agents", json!({})));
+            assert_eq!(read_framed(&mut framed_in)["id"], 2);
+
+            // Agents outlive the client that created them
+            drop(framed_in);
+            drop(framed);
+            std::thread::sleep(Duration::from_millis(100));
+            writeln!(raw, "{}", call(2, "list_agents", json!({}))).unwrap();
+            let resp = read_line_json(&mut raw_in);
+            assert_eq!(
+                resp["result"]["structuredContent"]["agents"][0]["agent_id"],
+                agent_id.as_str()
+            );
+            agent_id
+        })
+        .await
+        .unwrap();
+
+        // A second daemon refuses to take over a live socket
+        let other = McpServer::new(manager.clone(), Arc::new(NoSummarizer));
+        assert!(serve_unix(other, &path).await.is_err());
+
+        manager
+            .stop(&agent_id, crate::agents::manager::StopSignal::Kill, None)
+            .await
+            .unwrap();
+        daemon.abort();
+    }
+}
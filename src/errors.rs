@@ -1,3 +1,4 @@
+use serde::Serialize;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -18,6 +19,115 @@ pub enum SummarizeError {
     Unavailable,
     #[error("http error: {0}")]
     Http(String),
+    #[error("cancelled")]
+    Cancelled,
     #[error("other: {0}")]
     Other(String),
 }
+
+/// Machine-readable description of a failed tool call, returned to the client alongside the
+/// message so callers can branch on `code` instead of parsing text.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ErrorData {
+    /// Stable identifier; never changes for a given kind of failure
+    pub code: &'static str,
+    pub message: String,
+    /// Whether repeating the same call unchanged may succeed
+    pub retryable: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hint: Option<&'static str>,
+}
+
+impl AgentError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            AgentError::NotFound(_) => "not_found",
+            AgentError::Spawn(_) => "spawn_failed",
+            AgentError::Io(_) => "io_error",
+            AgentError::InvalidState(_) => "invalid_state",
+        }
+    }
+
+    pub fn retryable(&self) -> bool {
+        matches!(self, AgentError::Io(_))
+    }
+
+    pub fn hint(&self) -> Option<&'static str> {
+        match self {
+            AgentError::NotFound(_) => {
+                Some("check the agent_id; list_agents shows running agents and recent reaps")
+            }
+            AgentError::Spawn(_) => Some(
+                "cursor-agent not found or not executable; install it on PATH or set CURSOR_AGENT_PATH",
+            ),
+            AgentError::Io(_) => None,
+            AgentError::InvalidState(_) => None,
+        }
+    }
+
+    pub fn data(&self) -> ErrorData {
+        ErrorData {
+            code: self.code(),
+            message: self.to_string(),
+            retryable: self.retryable(),
+            hint: self.hint(),
+        }
+    }
+}
+
+impl SummarizeError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            SummarizeError::Unavailable => "summarizer_unavailable",
+            SummarizeError::Http(_) => "summarizer_http",
+            SummarizeError::Cancelled => "cancelled",
+            SummarizeError::Other(_) => "summarizer_failed",
+        }
+    }
+
+    pub fn retryable(&self) -> bool {
+        matches!(self, SummarizeError::Http(_))
+    }
+
+    pub fn hint(&self) -> Option<&'static str> {
+        match self {
+            SummarizeError::Unavailable => Some(
+                "the SUMMARY_BACKEND binary or service is missing; run health_check, or use SUMMARY_BACKEND=extractive",
+            ),
+            SummarizeError::Http(_) => Some("check that Ollama is running at OLLAMA_HOST"),
+            SummarizeError::Cancelled => None,
+            SummarizeError::Other(_) => None,
+        }
+    }
+
+    pub fn data(&self) -> ErrorData {
+        ErrorData {
+            code: self.code(),
+            message: self.to_string(),
+            retryable: self.retryable(),
+            hint: self.hint(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn error_data_carries_code_retryable_and_hint() {
+        let spawn = AgentError::Spawn("no such file".into()).data();
+        assert_eq!(spawn.code, "spawn_failed");
+        assert!(!spawn.retryable);
+        assert!(spawn.hint.unwrap().contains("CURSOR_AGENT_PATH"));
+
+        let http = serde_json::to_value(SummarizeError::Http("refused".into()).data()).unwrap();
+        assert_eq!(http["code"], "summarizer_http");
+        assert_eq!(http["retryable"], true);
+
+        // No hint means no key, not null
+        let io = serde_json::to_value(AgentError::Io("broken pipe".into()).data()).unwrap();
+        assert!(io.get("hint").is_none());
+        assert_eq!(io["message"], "io error: broken pipe");
+    }
+}
@@ -0,0 +1,330 @@
+// MCP streamable HTTP transport. Clients POST JSON-RPC messages to `/mcp` and may keep a GET
+// SSE stream open for server notifications. Each client gets its own session (initialization,
+// subscriptions, cancellation) over the shared agent manager, so agents outlive any one client.
+use crate::mcp::{self, Incoming, McpServer};
+use axum::body::Bytes;
+use axum::extract::{DefaultBodyLimit, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::routing::post;
+use axum::{Json, Router};
+use dashmap::DashMap;
+use parking_lot::Mutex;
+use serde_json::Value;
+use std::convert::Infallible;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use tokio_stream::StreamExt;
+
+pub const MCP_PATH: &str = "/mcp";
+const SESSION_HEADER: &str = "mcp-session-id";
+
+#[derive(Clone)]
+pub struct HttpTransport {
+    server: McpServer,
+    sessions: Arc<DashMap<String, Arc<HttpSession>>>,
+    session_idle: Option<Duration>,
+}
+
+struct HttpSession {
+    server: McpServer,
+    // Sender feeding the open GET stream; notifications are dropped while none is open
+    stream: Arc<Mutex<Option<mpsc::UnboundedSender<Value>>>>,
+    notifier: JoinHandle<()>,
+    relay: JoinHandle<()>,
+    // When the last request finished, and how many are still running
+    last_active: Mutex<Instant>,
+    in_flight: AtomicUsize,
+}
+
+// Marks a session busy for as long as one of its requests runs
+struct Activity<'a>(&'a HttpSession);
+
+impl Drop for Activity<'_> {
+    fn drop(&mut self) {
+        *self.0.last_active.lock() = Instant::now();
+        self.0.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl HttpSession {
+    fn new(server: McpServer) -> Self {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let notifier = server.spawn_notifier(tx);
+        let stream: Arc<Mutex<Option<mpsc::UnboundedSender<Value>>>> = Arc::default();
+        let current = stream.clone();
+        let relay = tokio::spawn(async move {
+            while let Some(msg) = rx.recv().await {
+                if let Some(tx) = current.lock().as_ref() {
+                    let _ = tx.send(msg);
+                }
+            }
+        });
+        Self {
+            server,
+            stream,
+            notifier,
+            relay,
+            last_active: Mutex::new(Instant::now()),
+            in_flight: AtomicUsize::new(0),
+        }
+    }
+
+    fn activity(&self) -> Activity<'_> {
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        Activity(self)
+    }
+
+    // No request running, no GET stream open, and nothing received for `window`
+    fn is_idle(&self, window: Duration) -> bool {
+        self.in_flight.load(Ordering::Relaxed) == 0
+            && self.stream.lock().as_ref().is_none_or(|tx| tx.is_closed())
+            && self.last_active.lock().elapsed() >= window
+    }
+}
+
+impl Drop for HttpSession {
+    fn drop(&mut self) {
+        self.notifier.abort();
+        self.relay.abort();
+    }
+}
+
+impl HttpTransport {
+    pub fn new(server: McpServer) -> Self {
+        Self {
+            server,
+            sessions: Arc::default(),
+            session_idle: None,
+        }
+    }
+
+    /// Drop sessions that have had no open stream and no requests for longer than `window`.
+    pub fn with_session_idle(mut self, window: Option<Duration>) -> Self {
+        self.session_idle = window;
+        self
+    }
+
+    pub fn router(&self) -> Router {
+        Router::new()
+            .route(
+                MCP_PATH,
+                post(post_message).get(open_stream).delete(close_session),
+            )
+            // Larger bodies are refused with 413 before they are buffered
+            .layer(DefaultBodyLimit::max(self.server.max_message_bytes()))
+            .with_state(self.clone())
+    }
+
+    /// Accept connections on `listener` until the server fails.
+    pub async fn serve(self, listener: TcpListener) -> anyhow::Result<()> {
+        tracing::info!(addr=%listener.local_addr()?, path=MCP_PATH, "serving MCP over HTTP");
+        let expiry = self.session_idle.map(|window| {
+            let this = self.clone();
+            let period = (window / 4).clamp(Duration::from_millis(10), Duration::from_secs(30));
+            tokio::spawn(async move {
+                let mut ticker = tokio::time::interval(period);
+                ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+                loop {
+                    ticker.tick().await;
+                    this.expire_idle_sessions(window);
+                }
+            })
+        });
+        let served = axum::serve(listener, self.router()).await;
+        if let Some(task) = expiry {
+            task.abort();
+        }
+        served?;
+        Ok(())
+    }
+
+    /// Remove every session idle for longer than `window`, returning how many were removed.
+    /// Clients of an expired session get 404 and start over with a new initialize.
+    pub fn expire_idle_sessions(&self, window: Duration) -> usize {
+        let before = self.sessions.len();
+        self.sessions.retain(|id, session| {
+            let idle = session.is_idle(window);
+            if idle {
+                tracing::info!(session=%id, "expiring idle http session");
+            }
+            !idle
+        });
+        before.saturating_sub(self.sessions.len())
+    }
+
+    fn session(
+        &self,
+        headers: &HeaderMap,
+    ) -> Result<(String, Arc<HttpSession>), (StatusCode, &'static str)> {
+        let Some(id) = headers.get(SESSION_HEADER).and_then(|v| v.to_str().ok()) else {
+            return Err((StatusCode::BAD_REQUEST, "missing Mcp-Session-Id header"));
+        };
+        // 404 tells the client to start over with a new initialize
+        match self.sessions.get(id) {
+            Some(s) => Ok((id.to_string(), s.clone())),
+            None => Err((StatusCode::NOT_FOUND, "unknown session")),
+        }
+    }
+}
+
+async fn post_message(State(t): State<HttpTransport>, headers: HeaderMap, body: Bytes) -> Response {
+    if !origin_allowed(&headers) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    let msg = match serde_json::from_slice::<Value>(&body) {
+        Ok(Value::Array(batch)) => {
+            // Batches may not carry initialize, so they always belong to an existing session
+            let (id, session) = match t.session(&headers) {
+                Ok(s) => s,
+                Err(e) => return e.into_response(),
+            };
+            let _active = session.activity();
+            return match session.server.handle_batch(batch).await {
+                Some(resp) => ([(SESSION_HEADER, id)], Json(resp)).into_response(),
+                None => StatusCode::ACCEPTED.into_response(),
+            };
+        }
+        Ok(v) => v,
+        Err(_) => return rpc_error(mcp::error_response(Value::Null, -32700, "Parse error")),
+    };
+    let msg = match Incoming::classify(msg) {
+        Incoming::Request(msg) | Incoming::Notification(msg) => msg,
+        Incoming::Response => return StatusCode::ACCEPTED.into_response(),
+        Incoming::Invalid(err) => return rpc_error(err),
+    };
+    let method = msg.get("method").and_then(|m| m.as_str());
+    let (id, session) = if method == Some("initialize") {
+        let id = uuid::Uuid::new_v4().to_string();
+        let session = Arc::new(HttpSession::new(t.server.new_session()));
+        t.sessions.insert(id.clone(), session.clone());
+        (id, session)
+    } else {
+        match t.session(&headers) {
+            Ok(s) => s,
+            Err(e) => return e.into_response(),
+        }
+    };
+    let _active = session.activity();
+    if msg.get("id").is_none_or(|id| id.is_null()) {
+        // Notifications are acknowledged without a body
+        session.server.handle_message(msg).await;
+        return StatusCode::ACCEPTED.into_response();
+    }
+    match session.server.handle_request(msg).await {
+        Some(resp) => ([(SESSION_HEADER, id)], Json(resp)).into_response(),
+        // Cancelled; the client no longer expects a result
+        None => StatusCode::ACCEPTED.into_response(),
+    }
+}
+
+async fn open_stream(State(t): State<HttpTransport>, headers: HeaderMap) -> Response {
+    if !origin_allowed(&headers) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    let session = match t.session(&headers) {
+        Ok((_, s)) => s,
+        Err(e) => return e.into_response(),
+    };
+    let (tx, rx) = mpsc::unbounded_channel();
+    // A new stream replaces (and so ends) any previous one for this session
+    *session.stream.lock() = Some(tx);
+    let events = UnboundedReceiverStream::new(rx)
+        .map(|msg| Ok::<_, Infallible>(Event::default().data(msg.to_string())));
+    Sse::new(events)
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+async fn close_session(State(t): State<HttpTransport>, headers: HeaderMap) -> Response {
+    if !origin_allowed(&headers) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    match t.session(&headers) {
+        Ok((id, _)) => {
+            t.sessions.remove(&id);
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(e) => e.into_response(),
+    }
+}
+
+fn rpc_error(body: Value) -> Response {
+    (StatusCode::BAD_REQUEST, Json(body)).into_response()
+}
+
+// Browsers send Origin; only pages served from this machine are accepted, so a remote site
+// cannot drive local agents through DNS rebinding
+fn origin_allowed(headers: &HeaderMap) -> bool {
+    let Some(origin) = headers.get(header::ORIGIN) else {
+        return true;
+    };
+    let host = origin
+        .to_str()
+        .ok()
+        .and_then(|o| url::Url::parse(o).ok())
+        .and_then(|u| u.host_str().map(str::to_owned));
+    matches!(host.as_deref(), Some("localhost" | "127.0.0.1" | "[::1]"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agents::manager::AgentManagerImpl;
+    use axum::http::HeaderValue;
+
+    #[tokio::test]
+    async fn idle_sessions_expire_unless_busy_or_streaming() {
+        let manager = Arc::new(AgentManagerImpl::new(None, 64 * 1024));
+        let summarizer =
+            crate::summarize::build_summarizer("extractive".into(), String::new(), String::new());
+        let t = HttpTransport::new(McpServer::new(manager, summarizer));
+        let session = |id: &str| {
+            let s = Arc::new(HttpSession::new(t.server.new_session()));
+            t.sessions.insert(id.into(), s.clone());
+            s
+        };
+        session("idle");
+        let busy = session("busy");
+        let streaming = session("streaming");
+        let (tx, _rx) = mpsc::unbounded_channel();
+        *streaming.stream.lock() = Some(tx);
+        let active = busy.activity();
+        let window = Duration::from_millis(20);
+
+        assert_eq!(t.expire_idle_sessions(window), 0);
+        tokio::time::sleep(window * 2).await;
+        assert_eq!(t.expire_idle_sessions(window), 1);
+        assert!(!t.sessions.contains_key("idle"));
+
+        // Finishing a request restarts the clock; a closed stream no longer keeps a session
+        drop(active);
+        drop(_rx);
+        assert_eq!(t.expire_idle_sessions(window), 1);
+        assert!(t.sessions.contains_key("busy"));
+        tokio::time::sleep(window * 2).await;
+        assert_eq!(t.expire_idle_sessions(window), 1);
+        assert!(t.sessions.is_empty());
+    }
+
+    #[test]
+    fn only_local_origins_are_allowed() {
+        let with_origin = |o: &str| {
+            let mut h = HeaderMap::new();
+            h.insert(header::ORIGIN, HeaderValue::from_str(o).unwrap());
+            h
+        };
+        assert!(origin_allowed(&HeaderMap::new()));
+        assert!(origin_allowed(&with_origin("http://localhost:3000")));
+        assert!(origin_allowed(&with_origin("http://127.0.0.1")));
+        assert!(origin_allowed(&with_origin("http://[::1]:8080")));
+        assert!(!origin_allowed(&with_origin("https://evil.example")));
+        assert!(!origin_allowed(&with_origin("null")));
+    }
+}
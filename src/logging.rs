@@ -1,5 +1,16 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::sync::OnceLock;
+use tokio::sync::broadcast;
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::layer::{Context, Layer};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
 
+// Log records buffered per client before the slowest one starts missing some
+const LOG_CHANNEL_CAPACITY: usize = 256;
+
 pub fn init_logging() {
     let silent = std::env::var("MCP_SILENT").ok().as_deref() == Some("1")
         || std::env::var("MCP_LOG")
@@ -21,12 +32,173 @@ pub fn init_logging() {
         .with_thread_ids(false)
         .with_thread_names(false);
 
+    // MCP clients get their own feed, filtered per session by `logging/setLevel`, whether or not
+    // stderr is silenced
+    let mcp_filter = Targets::new()
+        .with_target("cursor_mcp_subagents", Level::DEBUG)
+        .with_default(Level::WARN);
+
+    let registry = tracing_subscriber::registry().with(McpLogLayer.with_filter(mcp_filter));
     if silent {
-        tracing_subscriber::registry().with(env_filter).init();
+        registry.init();
     } else {
-        tracing_subscriber::registry()
-            .with(env_filter)
-            .with(fmt_layer)
-            .init();
+        registry.with(fmt_layer.with_filter(env_filter)).init();
+    }
+}
+
+/// MCP log severities (the syslog levels of RFC 5424), least severe first.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Debug,
+    Info,
+    Notice,
+    /// What clients receive until they call `logging/setLevel`
+    #[default]
+    Warning,
+    Error,
+    Critical,
+    Alert,
+    Emergency,
+}
+
+impl From<&Level> for LogLevel {
+    fn from(level: &Level) -> Self {
+        match *level {
+            Level::ERROR => Self::Error,
+            Level::WARN => Self::Warning,
+            Level::INFO => Self::Info,
+            _ => Self::Debug,
+        }
+    }
+}
+
+/// One tracing event, ready to be sent to clients as `notifications/message`.
+#[derive(Debug, Clone)]
+pub struct LogRecord {
+    pub level: LogLevel,
+    /// Module that emitted the event
+    pub logger: String,
+    /// Agent the event is about, when it names one
+    pub agent_id: Option<String>,
+    /// The message and the event's fields
+    pub data: Value,
+}
+
+impl LogRecord {
+    /// Params of the `notifications/message` carrying this record. Events about an agent are
+    /// logged under `agent/<agent_id>` so clients can tell agents apart.
+    pub fn to_params(&self) -> Value {
+        let logger = match &self.agent_id {
+            Some(id) => format!("agent/{id}"),
+            None => self.logger.clone(),
+        };
+        json!({"level": self.level, "logger": logger, "data": self.data})
+    }
+}
+
+fn log_sender() -> &'static broadcast::Sender<LogRecord> {
+    static LOGS: OnceLock<broadcast::Sender<LogRecord>> = OnceLock::new();
+    LOGS.get_or_init(|| broadcast::channel(LOG_CHANNEL_CAPACITY).0)
+}
+
+/// Receive every event seen by `McpLogLayer` from now on.
+pub fn subscribe_logs() -> broadcast::Receiver<LogRecord> {
+    log_sender().subscribe()
+}
+
+/// Tracing layer that publishes events to `subscribe_logs` receivers.
+pub struct McpLogLayer;
+
+impl<S: Subscriber> Layer<S> for McpLogLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let logs = log_sender();
+        // Skip the formatting when no client is listening
+        if logs.receiver_count() == 0 {
+            return;
+        }
+        let mut fields = FieldMap::default();
+        event.record(&mut fields);
+        let agent_id = fields.0.get("agent_id").map(|v| {
+            v.as_str()
+                .map(str::to_owned)
+                .unwrap_or_else(|| v.to_string())
+        });
+        let _ = logs.send(LogRecord {
+            level: event.metadata().level().into(),
+            logger: event.metadata().target().to_string(),
+            agent_id,
+            data: Value::Object(fields.0),
+        });
+    }
+}
+
+#[derive(Default)]
+struct FieldMap(Map<String, Value>);
+
+impl Visit for FieldMap {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.0
+            .insert(field.name().into(), format!("{value:?}").into());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_become_log_records_with_agent_ids() {
+        let mut logs = subscribe_logs();
+        let subscriber = tracing_subscriber::registry().with(McpLogLayer);
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::warn!(agent_id = "a1", exit_code = 3, "logging test event");
+            tracing::debug!(count = 2u64, "logging test without agent");
+        });
+        let mine = std::iter::from_fn(|| logs.try_recv().ok())
+            .filter(|r| {
+                r.data["message"]
+                    .as_str()
+                    .unwrap_or("")
+                    .starts_with("logging test")
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(mine.len(), 2);
+        assert_eq!(mine[0].level, LogLevel::Warning);
+        assert_eq!(mine[0].agent_id.as_deref(), Some("a1"));
+        assert_eq!(mine[0].data["exit_code"], 3);
+        let params = mine[0].to_params();
+        assert_eq!(params["level"], "warning");
+        assert_eq!(params["logger"], "agent/a1");
+        assert_eq!(params["data"]["agent_id"], "a1");
+        assert_eq!(mine[1].level, LogLevel::Debug);
+        assert_eq!(mine[1].agent_id, None);
+        assert_eq!(mine[1].to_params()["logger"], module_path!());
+
+        assert!(LogLevel::Error > LogLevel::Warning);
+        assert_eq!(
+            serde_json::from_value::<LogLevel>(json!("emergency")).unwrap(),
+            LogLevel::Emergency
+        );
     }
 }
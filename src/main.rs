@@ -2,15 +2,20 @@ use std::sync::Arc;
 
 mod agents;
 mod config;
+#[cfg(unix)]
+mod daemon;
 mod errors;
 mod health;
+mod http;
 mod logging;
 mod mcp;
 mod summarize;
 
 use crate::agents::manager::AgentManagerImpl;
-use crate::config::AppConfig;
-use crate::mcp::StdioMcpServer;
+use crate::agents::transcript::TranscriptConfig;
+use crate::config::{AppConfig, Transport};
+use crate::http::HttpTransport;
+use crate::mcp::McpServer;
 use crate::summarize::build_summarizer;
 
 #[tokio::main]
@@ -25,10 +30,21 @@ async fn main() {
         cfg.ollama_host.clone(),
     );
 
-    let agent_manager = Arc::new(AgentManagerImpl::new(
-        cfg.cursor_agent_path.clone(),
-        cfg.buffer_bytes as usize,
-    ));
+    let agent_manager = Arc::new(
+        AgentManagerImpl::new(cfg.cursor_agent_path.clone(), cfg.buffer_bytes as usize)
+            .with_idle_reap(
+                cfg.idle_reap_mins
+                    .filter(|m| *m > 0)
+                    .map(|m| std::time::Duration::from_secs(u64::from(m) * 60)),
+            )
+            .with_stop_grace(std::time::Duration::from_millis(cfg.stop_grace_ms))
+            .with_transcripts(cfg.transcript_dir.clone().map(|dir| TranscriptConfig {
+                dir,
+                max_bytes: cfg.transcript_max_bytes,
+                max_files: cfg.transcript_max_files,
+            })),
+    );
+    agent_manager.spawn_idle_reaper();
 
     // Startup health checks (best-effort, logged only)
     let cursor_ok = health::check_cursor_agent(cfg.cursor_agent_path.as_deref());
@@ -48,21 +64,54 @@ async fn main() {
         llama_cpp_ok=llama_ok,
         summary_backend=%cfg.summary_backend,
         buffer_size=%cfg.buffer_bytes,
+        idle_reap_mins=?cfg.idle_reap_mins,
+        transcript_dir=?cfg.transcript_dir,
+        transport=?cfg.transport,
         "MCP server startup complete"
     );
 
     if let Err(e) = cfg.validate() {
         tracing::warn!(config_error=%e, "invalid config");
     }
-    let server = StdioMcpServer::new(agent_manager.clone(), summarizer);
-    // Graceful shutdown without spawning (run future is not Send due to stdio locks)
+    let server = McpServer::new(agent_manager.clone(), summarizer)
+        .with_notify_debounce(std::time::Duration::from_millis(cfg.notify_debounce_ms))
+        .with_max_concurrency(cfg.max_concurrent_requests)
+        .with_max_message_bytes(cfg.max_message_bytes);
+    let serving = async {
+        match cfg.transport {
+            Transport::Stdio => server.run().await,
+            Transport::Http => {
+                let listener = tokio::net::TcpListener::bind(cfg.listen).await?;
+                HttpTransport::new(server)
+                    .with_session_idle(
+                        Some(cfg.http_session_idle_mins)
+                            .filter(|m| *m > 0)
+                            .map(|m| std::time::Duration::from_secs(u64::from(m) * 60)),
+                    )
+                    .serve(listener)
+                    .await
+            }
+            #[cfg(unix)]
+            Transport::Unix => {
+                let path = cfg
+                    .socket
+                    .clone()
+                    .ok_or_else(|| anyhow::anyhow!("--socket is required with --transport unix"))?;
+                daemon::serve_unix(server, &path).await
+            }
+            #[cfg(not(unix))]
+            Transport::Unix => anyhow::bail!("unix sockets are not supported on this platform"),
+        }
+    };
+    // Graceful shutdown without spawning
     tokio::select! {
-        res = server.run() => {
+        res = serving => {
             if let Err(e) = res { tracing::error!(error=?e, "server terminated with error") }
+            tracing::info!("Transport closed, stopping all agents...");
         }
         _ = tokio::signal::ctrl_c() => {
             tracing::info!("Received shutdown signal, stopping all agents...");
-            agent_manager.stop_all().await;
         }
     }
+    agent_manager.stop_all().await;
 }
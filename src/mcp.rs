@@ -1,248 +1,804 @@
 use crate::agents::manager::{AgentManagerImpl, StopSignal};
-use crate::agents::model::CreateAgentRequest;
+use crate::agents::model::{
+    AgentEvent, AgentEventKind, AgentSignal, AgentSummary, CreateAgentRequest, ExpectStep,
+    OutputFilter, PtySize, ResourceLimits, RestartPolicy, WaitCondition,
+};
+use crate::errors::{AgentError, ErrorData, SummarizeError};
 use crate::health;
+use crate::logging::{self, LogLevel};
 use crate::summarize::Summarizer;
+use parking_lot::Mutex;
 use serde::Deserialize;
-use serde_json::json;
-use std::io::Write;
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::io::{Read, Write};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{mpsc, OwnedSemaphorePermit, Semaphore};
+use tokio_util::sync::CancellationToken;
 
-// Global switch: once we detect raw JSON (no Content-Length) from the client,
-// we reply in ND-JSON (one JSON per line, no headers).
-static RAW_JSON_MODE: AtomicBool = AtomicBool::new(false);
+const AGENTS_URI: &str = "mcp://cursor-mcp-subagents/agents";
+// Default window over which agent events are coalesced into resource notifications
+const DEFAULT_NOTIFY_DEBOUNCE: Duration = Duration::from_millis(250);
+// Default number of requests handled at the same time
+const DEFAULT_MAX_CONCURRENCY: usize = 16;
+// Default size limit for one incoming message (or batch)
+const DEFAULT_MAX_MESSAGE_BYTES: usize = 4 * 1024 * 1024;
 
-pub struct StdioMcpServer {
+#[derive(Clone)]
+pub struct McpServer {
     manager: Arc<AgentManagerImpl>,
     summarizer: Arc<dyn Summarizer>,
+    session: Arc<Session>,
+    notify_debounce: Duration,
+    // Shared by every session, so the cap holds across all clients
+    slots: Arc<Semaphore>,
+    max_message_bytes: usize,
 }
 
-impl StdioMcpServer {
+// Protocol versions this server speaks, newest first
+const SUPPORTED_PROTOCOL_VERSIONS: [&str; 3] = ["2025-06-18", "2025-03-26", "2024-11-05"];
+
+/// Where a client is in the MCP lifecycle.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum Lifecycle {
+    /// Only `initialize` (and `ping`) are accepted
+    #[default]
+    New,
+    /// `initialize` was answered; waiting for `notifications/initialized`
+    Initializing,
+    /// Fully initialized; server notifications may be sent
+    Ready,
+}
+
+/// What the client told us about itself in `initialize`.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ClientInfo {
+    pub name: Option<String>,
+    pub version: Option<String>,
+    /// Version agreed on during negotiation
+    pub protocol_version: String,
+    /// Capabilities object exactly as the client sent it
+    pub capabilities: Value,
+}
+
+// Client state shared with the notification task
+#[derive(Default)]
+struct Session {
+    lifecycle: Mutex<Lifecycle>,
+    client: Mutex<Option<ClientInfo>>,
+    subscriptions: Mutex<HashSet<String>>,
+    // Least severe log level forwarded as notifications/message
+    log_level: Mutex<LogLevel>,
+    // Requests that notifications/cancelled may abort, keyed by their JSON-encoded id
+    in_flight: Mutex<HashMap<String, CancellationToken>>,
+}
+
+/// A request registered for cancellation but not yet answered. Dropping it, answered or not,
+/// unregisters it.
+pub struct PendingRequest {
+    req: Option<Value>,
+    cancel: CancellationToken,
+    registration: Option<(Arc<Session>, String)>,
+}
+
+impl Drop for PendingRequest {
+    fn drop(&mut self) {
+        if let Some((session, key)) = &self.registration {
+            session.in_flight.lock().remove(key);
+        }
+    }
+}
+
+/// One incoming JSON-RPC message, sorted by what the server has to do with it.
+pub enum Incoming {
+    /// Has a method and an id; expects a response
+    Request(Value),
+    /// Has a method but no id
+    Notification(Value),
+    /// A reply to a server request; the server sends none, so these are ignored
+    Response,
+    /// Not a valid JSON-RPC 2.0 message; holds the error response to send
+    Invalid(Value),
+}
+
+impl Incoming {
+    pub fn classify(msg: Value) -> Self {
+        let id = match msg.get("id") {
+            None => None,
+            Some(id @ (Value::Null | Value::Number(_) | Value::String(_))) => Some(id.clone()),
+            Some(_) => {
+                return Self::Invalid(error_response(
+                    Value::Null,
+                    -32600,
+                    "Invalid Request: id must be a string, number or null",
+                ))
+            }
+        };
+        let invalid = |message: &str| {
+            Self::Invalid(error_response(
+                id.clone().unwrap_or(Value::Null),
+                -32600,
+                message,
+            ))
+        };
+        if !msg.is_object() {
+            return invalid("Invalid Request: expected an object");
+        }
+        if msg.get("jsonrpc").and_then(|v| v.as_str()) != Some("2.0") {
+            return invalid("Invalid Request: jsonrpc must be \"2.0\"");
+        }
+        match msg.get("method") {
+            Some(Value::String(_)) => {}
+            Some(_) => return invalid("Invalid Request: method must be a string"),
+            None if msg.get("result").is_some() || msg.get("error").is_some() => {
+                return Self::Response
+            }
+            None => return invalid("Invalid Request: missing method"),
+        }
+        if id.as_ref().is_none_or(|id| id.is_null()) {
+            Self::Notification(msg)
+        } else {
+            Self::Request(msg)
+        }
+    }
+}
+
+pub fn error_response(id: Value, code: i64, message: &str) -> Value {
+    json!({"jsonrpc":"2.0","id": id, "error": {"code": code, "message": message}})
+}
+
+// A JSON-RPC error response
+#[derive(Debug)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl Session {
+    fn lifecycle(&self) -> Lifecycle {
+        *self.lifecycle.lock()
+    }
+
+    fn is_ready(&self) -> bool {
+        self.lifecycle() == Lifecycle::Ready
+    }
+}
+
+impl RpcError {
+    fn new(code: i64, message: &str) -> Self {
+        Self {
+            code,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl McpServer {
     pub fn new(manager: Arc<AgentManagerImpl>, summarizer: Arc<dyn Summarizer>) -> Self {
         Self {
             manager,
             summarizer,
+            session: Arc::new(Session::default()),
+            notify_debounce: DEFAULT_NOTIFY_DEBOUNCE,
+            slots: Arc::new(Semaphore::new(DEFAULT_MAX_CONCURRENCY)),
+            max_message_bytes: DEFAULT_MAX_MESSAGE_BYTES,
+        }
+    }
+
+    /// Reject incoming messages larger than `limit` bytes with an invalid-request error.
+    pub fn with_max_message_bytes(mut self, limit: usize) -> Self {
+        self.max_message_bytes = limit.max(1);
+        self
+    }
+
+    pub fn max_message_bytes(&self) -> usize {
+        self.max_message_bytes
+    }
+
+    /// Handle at most `limit` requests at once; further requests wait for a free slot.
+    pub fn with_max_concurrency(mut self, limit: usize) -> Self {
+        self.slots = Arc::new(Semaphore::new(limit.max(1)));
+        self
+    }
+
+    /// Coalesce agent output/status events over `window` before notifying subscribers.
+    pub fn with_notify_debounce(mut self, window: Duration) -> Self {
+        self.notify_debounce = window;
+        self
+    }
+
+    /// A server over the same agents and request cap, with fresh per-client state
+    /// (initialization, subscriptions, in-flight requests).
+    pub fn new_session(&self) -> Self {
+        Self {
+            session: Arc::new(Session::default()),
+            ..self.clone()
         }
     }
 
+    /// Serve a single client over stdin/stdout until stdin closes.
     pub async fn run(&self) -> anyhow::Result<()> {
-        let stdin = std::io::stdin();
-        let stdout = std::io::stdout();
-        let mut reader = std::io::BufReader::new(stdin.lock());
-        let mut writer = std::io::BufWriter::new(stdout.lock());
         tracing::info!("run loop started: waiting for framed MCP requests on stdin");
-        loop {
-            tracing::info!("waiting to parse next frame header");
-            let msg = match read_framed_message_buf(&mut reader) {
-                Ok(m) => m,
-                Err(e) => {
-                    tracing::debug!(error=?e, "stdin closed or invalid frame");
+        self.serve_stream(std::io::stdin(), std::io::stdout()).await;
+        Ok(())
+    }
+
+    /// Serve one client over a blocking byte stream until `reader` hits EOF. The client's framing
+    /// (Content-Length headers or ND-JSON) is detected for this stream alone.
+    pub async fn serve_stream<R, W>(&self, reader: R, writer: W)
+    where
+        R: Read + Send + 'static,
+        W: Write + Send + 'static,
+    {
+        let framing = Framing::new(self.max_message_bytes);
+        let (out_tx, out_rx) = mpsc::unbounded_channel::<Value>();
+        let (in_tx, in_rx) = mpsc::unbounded_channel::<Vec<u8>>();
+        // The stream is blocking; read and write it on dedicated threads so notifications can be
+        // written while the next request is awaited
+        let reader_framing = framing.clone();
+        let rejections = out_tx.clone();
+        std::thread::spawn(move || {
+            let mut reader = std::io::BufReader::new(reader);
+            loop {
+                match read_framed_message_buf(&mut reader, &reader_framing) {
+                    Ok(msg) => {
+                        if in_tx.send(msg).is_err() {
+                            break;
+                        }
+                    }
+                    // The oversized body or unusable headers were skipped, so the stream is still
+                    // in sync
+                    Err(e) if e.is::<FrameTooLarge>() || e.is::<InvalidFrame>() => {
+                        tracing::warn!(error=%e, "rejecting unreadable message");
+                        let _ =
+                            rejections.send(error_response(Value::Null, -32600, &e.to_string()));
+                    }
+                    Err(e) => {
+                        tracing::debug!(error=?e, "input closed or invalid frame");
+                        break;
+                    }
+                }
+            }
+        });
+        std::thread::spawn(move || {
+            let mut writer = std::io::BufWriter::new(writer);
+            let mut out_rx = out_rx;
+            while let Some(msg) = out_rx.blocking_recv() {
+                if let Err(e) = write_framed(&mut writer, &msg, &framing) {
+                    tracing::debug!(error=?e, "output closed");
                     break;
                 }
-            };
-            let req: serde_json::Value = match serde_json::from_slice(&msg) {
+            }
+        });
+        self.serve(in_rx, out_tx).await;
+    }
+
+    /// Read raw messages from `incoming` until it closes, writing responses and notifications to
+    /// `outgoing`. Requests and batches run concurrently (up to the configured cap) and respond
+    /// as they finish; notifications are handled in arrival order.
+    pub async fn serve(
+        &self,
+        mut incoming: mpsc::UnboundedReceiver<Vec<u8>>,
+        outgoing: mpsc::UnboundedSender<Value>,
+    ) {
+        let notifier = self.spawn_notifier(outgoing.clone());
+        // Dropping the set on return aborts requests still in flight
+        let mut in_flight = tokio::task::JoinSet::new();
+        while let Some(msg) = incoming.recv().await {
+            while in_flight.try_join_next().is_some() {}
+            let payload: Value = match serde_json::from_slice(&msg) {
                 Ok(v) => v,
                 Err(e) => {
                     tracing::warn!(error=?e, "invalid JSON");
+                    let _ = outgoing.send(error_response(Value::Null, -32700, "Parse error"));
                     continue;
                 }
             };
-
-            let method = req.get("method").and_then(|m| m.as_str()).unwrap_or("");
-            let id_opt = req.get("id").cloned();
-            let id_reply = id_opt.as_ref().filter(|v| !v.is_null()).cloned();
-            tracing::info!(%method, id=?id_opt, "received request");
-            match method {
-                "initialize" => {
-                    let params = req.get("params").cloned().unwrap_or(json!({}));
-                    let client_proto = params
-                        .get("protocolVersion")
-                        .and_then(|x| x.as_str())
-                        .unwrap_or("2024-11-05");
-                    let result = json!({
-                        "protocolVersion": client_proto,
-                        "capabilities": {
-                            "tools": {"list": true, "call": true},
-                            "prompts": {"list": true, "get": true},
-                            "resources": {"list": true, "read": true, "subscribe": false}
-                        },
-                        "serverInfo": {"name": "cursor-mcp-subagents", "version": env!("CARGO_PKG_VERSION")}
+            let req = match payload {
+                Value::Array(batch) => {
+                    let this = self.clone();
+                    let outgoing = outgoing.clone();
+                    in_flight.spawn(async move {
+                        if let Some(resp) = this.handle_batch(batch).await {
+                            let _ = outgoing.send(resp);
+                        }
                     });
-                    if let Some(id) = id_reply.clone() {
-                        write_response(&mut writer, id, result)?;
-                    }
+                    continue;
                 }
-                "server/info" => {
-                    let info = json!({"name": "cursor-mcp-subagents", "version": env!("CARGO_PKG_VERSION")});
-                    if let Some(id) = id_reply.clone() {
-                        write_response(&mut writer, id, json!({"serverInfo": info}))?;
+                msg => match Incoming::classify(msg) {
+                    Incoming::Request(req) => req,
+                    Incoming::Notification(msg) => {
+                        self.handle_message(msg).await;
+                        continue;
                     }
-                }
-                "tools/list" => {
-                    let tools = list_tools_schema();
-                    if let Some(id) = id_reply.clone() {
-                        write_response(&mut writer, id, json!({"tools": tools}))?;
+                    Incoming::Response => continue,
+                    Incoming::Invalid(err) => {
+                        let _ = outgoing.send(err);
+                        continue;
                     }
+                },
+            };
+            // Register before spawning so a cancel read right after this can already find the
+            // request; the slot is waited for in the task so the loop keeps reading cancels
+            let pending = self.register_request(req);
+            let this = self.clone();
+            let outgoing = outgoing.clone();
+            in_flight.spawn(async move {
+                if let Some(resp) = this.run_request(pending).await {
+                    let _ = outgoing.send(resp);
                 }
-                "prompts/list" => {
-                    let prompts = vec![json!({
-                        "name": "delegate_subagent_session",
-                        "description": "Start and manage a delegated subagent that tackles a scoped subtask using cursor-agent.",
-                        "arguments": [
-                            {"name": "task", "description": "Subtask description and concrete goals.", "required": true},
-                            {"name": "working_dir", "description": "Working directory for the subagent process.", "required": false},
-                            {"name": "args", "description": "Extra command line args for cursor-agent.", "required": false}
-                        ]
-                    })];
-                    if let Some(id) = id_reply.clone() {
-                        write_response(&mut writer, id, json!({"prompts": prompts}))?;
+            });
+        }
+        notifier.abort();
+    }
+
+    /// Send resource notifications for this session's subscriptions to `out` until aborted.
+    pub fn spawn_notifier(&self, out: mpsc::UnboundedSender<Value>) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(forward_notifications(
+            self.manager.clone(),
+            self.session.clone(),
+            out,
+            self.notify_debounce,
+        ))
+    }
+
+    // Wait for one of the concurrent request slots; it is released when the permit drops
+    async fn acquire_slot(&self) -> OwnedSemaphorePermit {
+        self.slots
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("request semaphore is never closed")
+    }
+
+    /// Handle a request that `notifications/cancelled` can abort, once a request slot is free. A
+    /// cancelled request gets no response.
+    pub async fn handle_request(&self, req: Value) -> Option<Value> {
+        self.run_request(self.register_request(req)).await
+    }
+
+    /// Make `req` cancellable by `notifications/cancelled` from now on, before it starts running.
+    pub fn register_request(&self, req: Value) -> PendingRequest {
+        let cancel = CancellationToken::new();
+        // The initialize request must not be cancelled
+        let key = req
+            .get("id")
+            .filter(|id| !id.is_null())
+            .filter(|_| req.get("method").and_then(|m| m.as_str()) != Some("initialize"))
+            .map(request_key);
+        if let Some(key) = &key {
+            self.session
+                .in_flight
+                .lock()
+                .insert(key.clone(), cancel.clone());
+        }
+        PendingRequest {
+            req: Some(req),
+            cancel,
+            registration: key.map(|key| (self.session.clone(), key)),
+        }
+    }
+
+    /// Wait for a request slot, then handle a registered request. Cancelling it while it waits
+    /// gives up the wait.
+    pub async fn run_request(&self, mut pending: PendingRequest) -> Option<Value> {
+        let req = pending.req.take()?;
+        let cancel = pending.cancel.clone();
+        tokio::select! {
+            resp = async {
+                let _slot = self.acquire_slot().await;
+                self.respond(req, &cancel).await
+            } => resp,
+            _ = cancel.cancelled() => None,
+        }
+    }
+
+    /// Handle a JSON-RPC batch. Its messages run concurrently, each taking a request slot; the
+    /// responses come back as one array in request order, or not at all if there are none.
+    pub async fn handle_batch(&self, batch: Vec<Value>) -> Option<Value> {
+        if batch.is_empty() {
+            return Some(error_response(
+                Value::Null,
+                -32600,
+                "Invalid Request: empty batch",
+            ));
+        }
+        let mut set = tokio::task::JoinSet::new();
+        for (i, msg) in batch.into_iter().enumerate() {
+            let this = self.clone();
+            set.spawn(async move {
+                let resp = match Incoming::classify(msg) {
+                    Incoming::Request(req)
+                        if req.get("method").and_then(|m| m.as_str()) == Some("initialize") =>
+                    {
+                        Some(error_response(
+                            req["id"].clone(),
+                            -32600,
+                            "Invalid Request: initialize cannot be batched",
+                        ))
                     }
+                    Incoming::Request(req) => this.handle_request(req).await,
+                    Incoming::Notification(msg) => this.handle_message(msg).await,
+                    Incoming::Response => None,
+                    Incoming::Invalid(err) => Some(err),
+                };
+                (i, resp)
+            });
+        }
+        let mut responses = Vec::new();
+        while let Some(joined) = set.join_next().await {
+            if let Ok((i, Some(resp))) = joined {
+                responses.push((i, resp));
+            }
+        }
+        responses.sort_by_key(|(i, _)| *i);
+        (!responses.is_empty()).then(|| responses.into_iter().map(|(_, r)| r).collect())
+    }
+
+    /// Handle one JSON-RPC message, returning the response to send (none for notifications).
+    pub async fn handle_message(&self, req: Value) -> Option<Value> {
+        self.respond(req, &CancellationToken::new()).await
+    }
+
+    // Like handle_message; long-running work gives up once `cancel` fires
+    async fn respond(&self, req: Value, cancel: &CancellationToken) -> Option<Value> {
+        let method = req.get("method").and_then(|m| m.as_str()).unwrap_or("");
+        let id_opt = req.get("id").cloned();
+        let id_reply = id_opt.as_ref().filter(|v| !v.is_null()).cloned();
+        tracing::info!(%method, id=?id_opt, "received request");
+        let params = req.get("params").cloned().unwrap_or(json!({}));
+        let result = self.handle_method(method, params, cancel).await;
+        // Do not respond to notifications (no id)
+        let id = id_reply?;
+        Some(match result {
+            Ok(result) => json!({"jsonrpc":"2.0","id": id, "result": result}),
+            Err(e) => error_response(id, e.code, &e.message),
+        })
+    }
+
+    async fn handle_method(
+        &self,
+        method: &str,
+        params: Value,
+        cancel: &CancellationToken,
+    ) -> Result<Value, RpcError> {
+        if self.session.lifecycle() == Lifecycle::New && !matches!(method, "initialize" | "ping") {
+            return Err(RpcError::new(
+                -32600,
+                "Server not initialized: send initialize first",
+            ));
+        }
+        match method {
+            "initialize" => {
+                let mut state = self.session.lifecycle.lock();
+                if *state != Lifecycle::New {
+                    return Err(RpcError::new(-32600, "Already initialized"));
                 }
-                "prompts/get" => {
-                    let params = req.get("params").cloned().unwrap_or(json!({}));
-                    let name = params.get("name").and_then(|x| x.as_str()).unwrap_or("");
-                    if name != "delegate_subagent_session" {
-                        if let Some(id) = id_reply.clone() {
-                            write_error(&mut writer, id, -32602, "Unknown prompt name")?;
-                        }
-                        continue;
-                    }
-                    let args = params.get("arguments").cloned().unwrap_or(json!({}));
-                    let task = args.get("task").and_then(|x| x.as_str()).unwrap_or("");
-                    let working_dir = args
-                        .get("working_dir")
+                let requested = params.get("protocolVersion").and_then(|x| x.as_str());
+                // Agree to the client's version when we speak it; otherwise answer with our newest
+                // and leave it to the client to disconnect if it cannot use that
+                let version = requested
+                    .filter(|v| SUPPORTED_PROTOCOL_VERSIONS.contains(v))
+                    .unwrap_or(SUPPORTED_PROTOCOL_VERSIONS[0]);
+                let info_field = |field: &str| {
+                    params
+                        .get("clientInfo")
+                        .and_then(|c| c.get(field))
                         .and_then(|x| x.as_str())
-                        .unwrap_or("");
-                    let guidance = format!(
+                        .map(str::to_owned)
+                };
+                let client = ClientInfo {
+                    name: info_field("name"),
+                    version: info_field("version"),
+                    protocol_version: version.to_string(),
+                    capabilities: params.get("capabilities").cloned().unwrap_or(json!({})),
+                };
+                tracing::info!(
+                    client=?client.name,
+                    client_version=?client.version,
+                    requested=?requested,
+                    protocol_version=%version,
+                    "client initializing"
+                );
+                *self.session.client.lock() = Some(client);
+                *state = Lifecycle::Initializing;
+                Ok(json!({
+                    "protocolVersion": version,
+                    "capabilities": {
+                        "tools": {"listChanged": false},
+                        "prompts": {"listChanged": false},
+                        "resources": {"subscribe": true, "listChanged": true},
+                        "logging": {}
+                    },
+                    "serverInfo": {"name": "cursor-mcp-subagents", "version": env!("CARGO_PKG_VERSION")}
+                }))
+            }
+            "ping" => Ok(json!({})),
+            "server/info" => {
+                let info =
+                    json!({"name": "cursor-mcp-subagents", "version": env!("CARGO_PKG_VERSION")});
+                let client = self.session.client.lock().clone();
+                Ok(json!({"serverInfo": info, "client": client}))
+            }
+            "tools/list" => {
+                let tools = list_tools_schema();
+                Ok(json!({"tools": tools}))
+            }
+            "prompts/list" => {
+                let prompts = vec![json!({
+                    "name": "delegate_subagent_session",
+                    "description": "Start and manage a delegated subagent that tackles a scoped subtask using cursor-agent.",
+                    "arguments": [
+                        {"name": "task", "description": "Subtask description and concrete goals.", "required": true},
+                        {"name": "working_dir", "description": "Working directory for the subagent process.", "required": false},
+                        {"name": "args", "description": "Extra command line args for cursor-agent.", "required": false}
+                    ]
+                })];
+                Ok(json!({"prompts": prompts}))
+            }
+            "prompts/get" => {
+                let name = params.get("name").and_then(|x| x.as_str()).unwrap_or("");
+                if name != "delegate_subagent_session" {
+                    return Err(RpcError::new(-32602, "Unknown prompt name"));
+                }
+                let args = params.get("arguments").cloned().unwrap_or(json!({}));
+                let task = args.get("task").and_then(|x| x.as_str()).unwrap_or("");
+                let working_dir = args
+                    .get("working_dir")
+                    .and_then(|x| x.as_str())
+                    .unwrap_or("");
+                let guidance = format!(
                         "You are delegating a scoped subtask to a persistent subagent (cursor-agent).\n\nTask: {task}\nWorking dir: {working_dir}\n\nUse the MCP server 'cursor-subagents' to manage the session:\n1) create_agent (set working_dir if provided; pass args if needed)\n2) send_agent_input to run concrete commands to advance the task\n3) get_agent_progress periodically with brief instructions to summarize current output\n4) reset_agent (soft) to clear noise, reset_agent (hard) to restart the process\n5) stop_agent when the delegated task is complete\n\nKeep the subagent focused on this subtask only. Summarize progress for the main agent and surface blockers early. Reuse an existing agent if appropriate (list_agents).",
                     );
-                    let messages = vec![json!({
-                        "role": "user",
-                        "content": [
-                            {"type": "text", "text": guidance}
-                        ]
-                    })];
-                    if let Some(id) = id_reply.clone() {
-                        write_response(&mut writer, id, json!({"messages": messages}))?;
+                let messages = vec![json!({
+                    "role": "user",
+                    "content": [
+                        {"type": "text", "text": guidance}
+                    ]
+                })];
+                Ok(json!({"messages": messages}))
+            }
+            "resources/list" => {
+                let mut resources = vec![
+                    json!({
+                        "uri": "mcp://cursor-mcp-subagents/metrics",
+                        "name": "Server metrics snapshot",
+                        "description": "Current metrics and counters for the MCP server",
+                        "mimeType": "application/json"
+                    }),
+                    json!({
+                        "uri": "mcp://cursor-mcp-subagents/agents",
+                        "name": "Active agents list",
+                        "description": "List of currently running agents managed by the server",
+                        "mimeType": "application/json"
+                    }),
+                    json!({
+                        "uri": "mcp://cursor-mcp-subagents/usage",
+                        "name": "Delegated subagent usage",
+                        "description": "How to use this server to delegate subtasks to cursor-agent",
+                        "mimeType": "text/markdown"
+                    }),
+                ];
+                for agent in self.manager.list().await {
+                    let label = agent.name.as_deref().unwrap_or(&agent.agent_id);
+                    for kind in AGENT_RESOURCES {
+                        if kind.name == "transcript" && agent.transcript_path.is_none() {
+                            continue;
+                        }
+                        resources.push(json!({
+                            "uri": agent_uri(&agent.agent_id, kind.name),
+                            "name": format!("{} of agent {label}", kind.title),
+                            "description": kind.description,
+                            "mimeType": kind.mime
+                        }));
                     }
                 }
-                "resources/list" => {
-                    let resources = vec![
-                        json!({
-                            "uri": "mcp://cursor-mcp-subagents/metrics",
-                            "name": "Server metrics snapshot",
-                            "description": "Current metrics and counters for the MCP server",
-                            "mimeType": "application/json"
-                        }),
-                        json!({
-                            "uri": "mcp://cursor-mcp-subagents/agents",
-                            "name": "Active agents list",
-                            "description": "List of currently running agents managed by the server",
-                            "mimeType": "application/json"
-                        }),
+                Ok(json!({"resources": resources}))
+            }
+            "resources/templates/list" => {
+                let templates: Vec<Value> = AGENT_RESOURCES
+                    .iter()
+                    .map(|kind| {
                         json!({
-                            "uri": "mcp://cursor-mcp-subagents/usage",
-                            "name": "Delegated subagent usage",
-                            "description": "How to use this server to delegate subtasks to cursor-agent",
-                            "mimeType": "text/markdown"
-                        }),
-                    ];
-                    if let Some(id) = id_reply.clone() {
-                        write_response(&mut writer, id, json!({"resources": resources}))?;
+                            "uriTemplate": agent_uri("{agent_id}", kind.name),
+                            "name": format!("Agent {}", kind.title.to_lowercase()),
+                            "description": kind.description,
+                            "mimeType": kind.mime
+                        })
+                    })
+                    .collect();
+                Ok(json!({"resourceTemplates": templates}))
+            }
+            "resources/read" => {
+                let uri = params.get("uri").and_then(|x| x.as_str()).unwrap_or("");
+                let (mime, text) = match uri {
+                    "mcp://cursor-mcp-subagents/metrics" => {
+                        let snap = self.manager.metrics_snapshot();
+                        (
+                            "application/json",
+                            serde_json::to_string_pretty(&snap).unwrap_or_else(|_| "{}".into()),
+                        )
                     }
-                }
-                "resources/read" => {
-                    let params = req.get("params").cloned().unwrap_or(json!({}));
-                    let uri = params.get("uri").and_then(|x| x.as_str()).unwrap_or("");
-                    let (mime, text) = match uri {
-                        "mcp://cursor-mcp-subagents/metrics" => {
-                            let snap = self.manager.metrics_snapshot();
-                            (
-                                "application/json",
-                                serde_json::to_string_pretty(&snap).unwrap_or_else(|_| "{}".into()),
-                            )
-                        }
-                        "mcp://cursor-mcp-subagents/agents" => {
-                            let list = self.manager.list().await;
-                            (
-                                "application/json",
-                                serde_json::to_string_pretty(&json!({"agents": list}))
-                                    .unwrap_or_else(|_| "{}".into()),
+                    "mcp://cursor-mcp-subagents/agents" => {
+                        let list = self.manager.list().await;
+                        let reaped = self.manager.reaped();
+                        (
+                            "application/json",
+                            serde_json::to_string_pretty(
+                                &json!({"agents": list, "reaped": reaped}),
                             )
-                        }
-                        "mcp://cursor-mcp-subagents/usage" => {
-                            let usage = r#"# Delegated Subagent (cursor-subagents)
+                            .unwrap_or_else(|_| "{}".into()),
+                        )
+                    }
+                    "mcp://cursor-mcp-subagents/usage" => {
+                        let usage = r#"# Delegated Subagent (cursor-subagents)
 
 Use this server to spawn and manage persistent child `cursor-agent` processes that focus on a scoped subtask of the main problem.
 
 Recommended flow:
-- create_agent: start a subagent (optionally set working_dir, args)
+- create_agent: start a subagent (optionally set working_dir, args, or `pty` for a terminal)
 - send_agent_input: run commands and interact with the subagent
 - get_agent_progress: summarize buffered output with brief instructions
+- read_agent_output: tail raw output lines incrementally using `next_cursor`
+- wait_for_output: block until output matches a pattern, goes quiet, or the agent exits
+- send_and_expect: script send/expect steps against the subagent in one call
 - reset_agent: soft clears buffer; hard restarts the process
 - list_agents: discover/attach to an existing subagent
+- signal_agent / close_agent_stdin: interrupt, pause/resume, or send end-of-input
+- resize_agent_pty: change the terminal size of a `pty` agent
 - stop_agent: terminate when done
 
 Tip: Keep each subagent narrowly scoped to a single delegated goal; summarize progress and blockers back to the main agent."#;
-                            ("text/markdown", usage.to_string())
-                        }
-                        _ => {
-                            if let Some(id) = id_reply.clone() {
-                                write_error(&mut writer, id, -32602, "Unknown resource uri")?;
-                            }
-                            continue;
-                        }
-                    };
-                    let contents = vec![json!({
-                        "uri": uri,
-                        "mimeType": mime,
-                        "text": text
-                    })];
-                    if let Some(id) = id_reply.clone() {
-                        write_response(&mut writer, id, json!({"contents": contents}))?;
+                        ("text/markdown", usage.to_string())
                     }
+                    other => self.read_agent_resource(other).await?,
+                };
+                let contents = vec![json!({
+                    "uri": uri,
+                    "mimeType": mime,
+                    "text": text
+                })];
+                Ok(json!({"contents": contents}))
+            }
+            "tools/call" => {
+                let name = params.get("name").and_then(|x| x.as_str()).unwrap_or("");
+                if !list_tools_schema().iter().any(|t| t["name"] == name) {
+                    return Err(RpcError::new(-32602, &format!("Unknown tool: {name}")));
                 }
-                "tools/call" => {
-                    let params = req.get("params").cloned().unwrap_or(json!({}));
-                    let name = params.get("name").and_then(|x| x.as_str()).unwrap_or("");
-                    let arguments = params.get("arguments").cloned().unwrap_or(json!({}));
-                    let result = self.dispatch_tool(name, arguments).await;
-                    match result {
-                        Ok(v) => {
-                            if let Some(id) = id_reply.clone() {
-                                write_response(
-                                    &mut writer,
-                                    id,
-                                    json!({"content": [{"type":"json","json": v}], "isError": false}),
-                                )?;
-                            }
-                        }
-                        Err(e) => {
-                            if let Some(id) = id_reply.clone() {
-                                write_error(&mut writer, id, -32001, &format!("{}", e))?;
-                            }
-                        }
+                let arguments = params.get("arguments").cloned().unwrap_or(json!({}));
+                let agent_id = arguments
+                    .get("agent_id")
+                    .and_then(|x| x.as_str())
+                    .map(str::to_owned);
+                // Failures of the tool itself are results the caller can react to, not
+                // protocol errors
+                match self.dispatch_tool(name, arguments, cancel).await {
+                    Ok(v) => Ok(tool_result(v)),
+                    Err(e) => {
+                        let data = error_data(&e);
+                        tracing::warn!(tool=%name, agent_id=agent_id.as_deref(), code=data.code, error=%e, "tool call failed");
+                        Ok(tool_error(data))
                     }
                 }
-                _ => {
-                    // Do not respond to notifications (no id)
-                    if let Some(id) = id_reply.clone() {
-                        write_error(&mut writer, id, -32601, "method not found")?;
-                    }
+            }
+            "logging/setLevel" => {
+                let level = params
+                    .get("level")
+                    .cloned()
+                    .and_then(|l| serde_json::from_value::<LogLevel>(l).ok())
+                    .ok_or_else(|| RpcError::new(-32602, "Invalid params: unknown log level"))?;
+                *self.session.log_level.lock() = level;
+                Ok(json!({}))
+            }
+            "resources/subscribe" => {
+                let uri = params.get("uri").and_then(|x| x.as_str()).unwrap_or("");
+                if !is_known_resource(uri) {
+                    return Err(RpcError::new(-32602, "Unknown resource uri"));
+                }
+                self.session.subscriptions.lock().insert(uri.to_string());
+                Ok(json!({}))
+            }
+            "resources/unsubscribe" => {
+                let uri = params.get("uri").and_then(|x| x.as_str()).unwrap_or("");
+                self.session.subscriptions.lock().remove(uri);
+                Ok(json!({}))
+            }
+            "notifications/initialized" => {
+                let mut state = self.session.lifecycle.lock();
+                if *state == Lifecycle::Initializing {
+                    *state = Lifecycle::Ready;
                 }
+                Ok(Value::Null)
+            }
+            "notifications/cancelled" => {
+                // Unknown or already finished requests are ignored
+                let request = params.get("requestId").map(request_key);
+                let token = request.and_then(|id| self.session.in_flight.lock().remove(&id));
+                if let Some(token) = token {
+                    let reason = params.get("reason").and_then(|r| r.as_str());
+                    tracing::info!(request_id=?params.get("requestId"), ?reason, "request cancelled");
+                    token.cancel();
+                    self.manager.record_cancelled_request();
+                }
+                Ok(Value::Null)
+            }
+            _ => Err(RpcError::new(-32601, "method not found")),
+        }
+    }
+
+    // Resolve a per-agent resource URI to (mime type, contents)
+    async fn read_agent_resource(&self, uri: &str) -> Result<(&'static str, String), RpcError> {
+        let Some(resource) = parse_agent_uri(uri) else {
+            return Err(RpcError::new(-32602, "Unknown resource uri"));
+        };
+        let agent_error = |e: AgentError| RpcError::new(-32001, &e.to_string());
+        match resource {
+            AgentResource::Output { agent_id, filter } => {
+                let text = self
+                    .manager
+                    .get_buffer(&agent_id, &filter)
+                    .await
+                    .map_err(agent_error)?;
+                Ok(("text/plain", text))
+            }
+            AgentResource::Summary { agent_id } => {
+                let summary = self
+                    .manager
+                    .latest_summary(&agent_id)
+                    .await
+                    .map_err(agent_error)?;
+                Ok((
+                    "application/json",
+                    serde_json::to_string_pretty(&summary).unwrap_or_else(|_| "null".into()),
+                ))
+            }
+            AgentResource::Metadata { agent_id } => {
+                let info = self.manager.info(&agent_id).await.map_err(agent_error)?;
+                Ok((
+                    "application/json",
+                    serde_json::to_string_pretty(&info).unwrap_or_else(|_| "{}".into()),
+                ))
+            }
+            AgentResource::Transcript {
+                agent_id,
+                from,
+                limit,
+                filter,
+            } => {
+                let page = self
+                    .manager
+                    .read_transcript(&agent_id, from, limit, &filter)
+                    .await
+                    .map_err(agent_error)?;
+                let mut ndjson = String::new();
+                for rec in &page.records {
+                    ndjson.push_str(&serde_json::to_string(rec).unwrap_or_default());
+                    ndjson.push('\n');
+                }
+                Ok(("application/x-ndjson", ndjson))
             }
         }
-        Ok(())
     }
 
     async fn dispatch_tool(
         &self,
         name: &str,
         arguments: serde_json::Value,
+        cancel: &CancellationToken,
     ) -> anyhow::Result<serde_json::Value> {
         match name {
             "create_agent" => {
                 let req: CreateAgentRequestWire = serde_json::from_value(arguments)?;
+                let pty = req.pty.unwrap_or(false);
+                if req.pty_size.is_some() && !pty {
+                    return Err(<serde_json::Error as serde::de::Error>::custom(
+                        "pty_size needs pty: true",
+                    )
+                    .into());
+                }
                 let resp = self
                     .manager
                     .create(CreateAgentRequest {
@@ -250,25 +806,82 @@ Tip: Keep each subagent narrowly scoped to a single delegated goal; summarize pr
                         working_dir: req.working_dir,
                         env: req.env.unwrap_or_default(),
                         args: req.args.unwrap_or_default(),
+                        idle_reap_exempt: req.idle_reap_exempt.unwrap_or(false),
+                        limits: req.limits.unwrap_or_default(),
+                        restart_policy: req.restart_policy.unwrap_or_default(),
+                        transcript: req.transcript,
+                        pty: pty.then(|| req.pty_size.unwrap_or_default()),
+                        keep_raw_output: req.keep_raw_output.unwrap_or(false),
                     })
                     .await?;
                 Ok(serde_json::to_value(resp)?)
             }
             "send_agent_input" => {
                 let p: SendAgentInput = serde_json::from_value(arguments)?;
-                self.manager.send_input(&p.agent_id, &p.input).await?;
-                Ok(json!({"accepted": true}))
+                if p.newline && !p.escapes {
+                    self.manager.send_input(&p.agent_id, &p.input).await?;
+                    return Ok(json!({"accepted": true, "bytes": p.input.len() + 1}));
+                }
+                let mut bytes = if p.escapes {
+                    decode_escapes(&p.input)
+                        .map_err(<serde_json::Error as serde::de::Error>::custom)?
+                } else {
+                    p.input.into_bytes()
+                };
+                if p.newline {
+                    bytes.push(b'\n');
+                }
+                self.manager.send_bytes(&p.agent_id, &bytes).await?;
+                Ok(json!({"accepted": true, "bytes": bytes.len()}))
+            }
+            "signal_agent" => {
+                let p: SignalAgent = serde_json::from_value(arguments)?;
+                let info = self.manager.signal(&p.agent_id, p.signal).await?;
+                Ok(json!({"agent_id": p.agent_id, "signal": p.signal, "status": info.status}))
+            }
+            "close_agent_stdin" => {
+                let p: CloseAgentStdin = serde_json::from_value(arguments)?;
+                let was_open = self.manager.close_stdin(&p.agent_id).await?;
+                Ok(json!({"closed": true, "already_closed": !was_open}))
+            }
+            "resize_agent_pty" => {
+                let p: ResizeAgentPty = serde_json::from_value(arguments)?;
+                let size = PtySize {
+                    rows: p.rows,
+                    cols: p.cols,
+                };
+                self.manager.resize_pty(&p.agent_id, size).await?;
+                Ok(json!({"agent_id": p.agent_id, "rows": size.rows, "cols": size.cols}))
             }
             "get_agent_progress" => {
                 let p: GetAgentProgress = serde_json::from_value(arguments)?;
-                let buf = self.manager.get_buffer(&p.agent_id).await?;
+                let buf = self.manager.get_buffer(&p.agent_id, &p.filter).await?;
                 let max_tokens = p.max_tokens.unwrap_or(1000).min(1000);
                 let summarizer = self.summarizer.clone();
                 let instructions = p.instructions.clone();
+                let cancel = cancel.clone();
+                // The blocking call cannot be aborted; the token makes it kill its subprocess
                 let res = tokio::task::spawn_blocking(move || {
-                    summarizer.summarize(&buf, instructions.as_deref(), max_tokens)
+                    summarizer.summarize_cancellable(
+                        &buf,
+                        instructions.as_deref(),
+                        max_tokens,
+                        &cancel,
+                    )
                 })
                 .await??;
+                self.manager
+                    .record_summary(
+                        &p.agent_id,
+                        AgentSummary {
+                            summary: res.summary.clone(),
+                            backend: res.backend.clone(),
+                            tokens_used: res.tokens_used,
+                            instructions: p.instructions,
+                            created_at: time::OffsetDateTime::now_utc(),
+                        },
+                    )
+                    .await?;
                 Ok(json!({
                     "summary": res.summary,
                     "tokens_used": res.tokens_used,
@@ -277,23 +890,58 @@ Tip: Keep each subagent narrowly scoped to a single delegated goal; summarize pr
             }
             "reset_agent" => {
                 let p: ResetAgent = serde_json::from_value(arguments)?;
-                self.manager
+                let outcome = self
+                    .manager
                     .reset(&p.agent_id, p.hard.unwrap_or(false))
                     .await?;
-                Ok(json!({"reset": if p.hard.unwrap_or(false) {"hard"} else {"soft"}}))
+                match outcome {
+                    Some(o) => Ok(json!({"reset": "hard", "stragglers": o.stragglers})),
+                    None => Ok(json!({"reset": "soft"})),
+                }
             }
             "stop_agent" => {
                 let p: StopAgent = serde_json::from_value(arguments)?;
-                let signal = match p.signal.as_deref() {
-                    Some("kill") => StopSignal::Kill,
-                    _ => StopSignal::Term,
-                };
-                self.manager.stop(&p.agent_id, signal).await?;
-                Ok(json!({"stopped": true}))
+                let signal = p.signal.unwrap_or(StopSignal::Term);
+                let grace = p.grace_ms.map(std::time::Duration::from_millis);
+                let outcome = self.manager.stop(&p.agent_id, signal, grace).await?;
+                Ok(json!({
+                    "stopped": true,
+                    "path": outcome.path,
+                    "exit_code": outcome.exit_code,
+                    "exit_signal": outcome.exit_signal,
+                    "stragglers": outcome.stragglers
+                }))
+            }
+            "read_agent_output" => {
+                let p: ReadAgentOutput = serde_json::from_value(arguments)?;
+                let page = self
+                    .manager
+                    .read_output(
+                        &p.agent_id,
+                        p.cursor.unwrap_or(0),
+                        p.limit.unwrap_or(DEFAULT_OUTPUT_LIMIT),
+                        &p.filter,
+                    )
+                    .await?;
+                Ok(serde_json::to_value(page)?)
+            }
+            "get_agent_transcript" => {
+                let p: GetAgentTranscript = serde_json::from_value(arguments)?;
+                let page = self
+                    .manager
+                    .read_transcript(
+                        &p.agent_id,
+                        p.from.unwrap_or(0),
+                        p.limit.unwrap_or(DEFAULT_TRANSCRIPT_LIMIT),
+                        &p.filter,
+                    )
+                    .await?;
+                Ok(serde_json::to_value(page)?)
             }
             "list_agents" => {
                 let list = self.manager.list().await;
-                Ok(json!({"agents": list}))
+                let reaped = self.manager.reaped();
+                Ok(json!({"agents": list, "reaped": reaped}))
             }
             "wait" => {
                 let p: WaitParams = serde_json::from_value(arguments)?;
@@ -309,6 +957,41 @@ Tip: Keep each subagent narrowly scoped to a single delegated goal; summarize pr
                 }
                 Ok(json!({"waited_ms": ms}))
             }
+            "wait_for_output" => {
+                let p: WaitForOutput = serde_json::from_value(arguments)?;
+                let cond = WaitCondition {
+                    pattern: p.pattern,
+                    idle: p.idle_ms.map(Duration::from_millis),
+                    exit: p.until_exit,
+                    filter: p.filter,
+                };
+                let timeout = p
+                    .timeout_ms
+                    .map(Duration::from_millis)
+                    .unwrap_or(DEFAULT_WAIT_TIMEOUT);
+                let outcome = self
+                    .manager
+                    .wait_for_output(&p.agent_id, p.cursor, &cond, timeout)
+                    .await?;
+                Ok(serde_json::to_value(outcome)?)
+            }
+            "send_and_expect" => {
+                let p: SendAndExpect = serde_json::from_value(arguments)?;
+                let steps: Vec<ExpectStep> = p
+                    .steps
+                    .into_iter()
+                    .map(|s| ExpectStep {
+                        send: s.send,
+                        expect: s.expect,
+                        timeout: s
+                            .timeout_ms
+                            .map(Duration::from_millis)
+                            .unwrap_or(DEFAULT_WAIT_TIMEOUT),
+                    })
+                    .collect();
+                let outcome = self.manager.send_and_expect(&p.agent_id, &steps).await?;
+                Ok(serde_json::to_value(outcome)?)
+            }
             "metrics" => {
                 let snap = self.manager.metrics_snapshot();
                 Ok(serde_json::to_value(snap)?)
@@ -333,48 +1016,549 @@ Tip: Keep each subagent narrowly scoped to a single delegated goal; summarize pr
     }
 }
 
+// Lines returned by read_agent_output when no limit is given
+const DEFAULT_OUTPUT_LIMIT: usize = 200;
+// Records returned by get_agent_transcript when no limit is given
+const DEFAULT_TRANSCRIPT_LIMIT: usize = 500;
+// wait_for_output and each send_and_expect step give up after this long unless told otherwise
+const DEFAULT_WAIT_TIMEOUT: Duration = Duration::from_secs(30);
+
+// A kind of per-agent resource, exposed as mcp://cursor-mcp-subagents/agents/{agent_id}/{name}
+struct AgentResourceKind {
+    name: &'static str,
+    title: &'static str,
+    description: &'static str,
+    mime: &'static str,
+}
+
+const AGENT_RESOURCES: [AgentResourceKind; 4] = [
+    AgentResourceKind {
+        name: "output",
+        title: "Output",
+        description: "Buffered stdout/stderr of the agent as text; filter with ?stream=stdout|stderr and RFC 3339 since=/until=",
+        mime: "text/plain",
+    },
+    AgentResourceKind {
+        name: "summary",
+        title: "Latest summary",
+        description: "Most recent get_agent_progress summary of the agent (null until one has been made)",
+        mime: "application/json",
+    },
+    AgentResourceKind {
+        name: "metadata",
+        title: "Metadata",
+        description: "Status, pid, exit info, limits and restart policy of the agent",
+        mime: "application/json",
+    },
+    AgentResourceKind {
+        name: "transcript",
+        title: "Transcript",
+        description: "Full output from disk as JSONL records; page with ?from=N&limit=M and filter with stream=, since=, until=. Requires TRANSCRIPT_DIR",
+        mime: "application/x-ndjson",
+    },
+];
+
+// Per-agent resources under mcp://cursor-mcp-subagents/agents/{agent_id}/...
+#[derive(Debug, PartialEq)]
+enum AgentResource {
+    /// Buffered output rendered as text
+    Output {
+        agent_id: String,
+        filter: OutputFilter,
+    },
+    Summary {
+        agent_id: String,
+    },
+    Metadata {
+        agent_id: String,
+    },
+    /// A page of the on-disk transcript as JSONL
+    Transcript {
+        agent_id: String,
+        from: u64,
+        limit: usize,
+        filter: OutputFilter,
+    },
+}
+
+fn agent_uri(agent_id: &str, kind: &str) -> String {
+    format!("{AGENTS_URI}/{agent_id}/{kind}")
+}
+
+// Subscriptions and notifications ignore the query part of a URI
+fn base_uri(uri: &str) -> &str {
+    uri.split_once('?').map_or(uri, |(base, _)| base)
+}
+
+fn is_known_resource(uri: &str) -> bool {
+    matches!(
+        uri,
+        "mcp://cursor-mcp-subagents/metrics" | AGENTS_URI | "mcp://cursor-mcp-subagents/usage"
+    ) || parse_agent_uri(uri).is_some()
+}
+
+// Parse `.../agents/{id}/{kind}`. Output and transcript accept `stream=stdout|stderr` and
+// RFC 3339 `since`/`until` filters; the transcript also pages with `from=N&limit=M`
+fn parse_agent_uri(uri: &str) -> Option<AgentResource> {
+    let url = url::Url::parse(uri).ok()?;
+    if url.scheme() != "mcp" || url.host_str() != Some("cursor-mcp-subagents") {
+        return None;
+    }
+    let mut segments = url.path_segments()?;
+    let (Some("agents"), Some(agent_id), Some(kind), None) = (
+        segments.next(),
+        segments.next(),
+        segments.next(),
+        segments.next(),
+    ) else {
+        return None;
+    };
+    let mut from = 0;
+    let mut limit = DEFAULT_TRANSCRIPT_LIMIT;
+    let mut filter = OutputFilter::default();
+    let rfc3339 = &time::format_description::well_known::Rfc3339;
+    for (k, v) in url.query_pairs() {
+        match k.as_ref() {
+            "from" => from = v.parse().ok()?,
+            "limit" => limit = v.parse().ok()?,
+            "stream" => filter.stream = serde_json::from_value(json!(v)).ok()?,
+            "since" => filter.since = Some(time::OffsetDateTime::parse(&v, rfc3339).ok()?),
+            "until" => filter.until = Some(time::OffsetDateTime::parse(&v, rfc3339).ok()?),
+            _ => {}
+        }
+    }
+    let agent_id = agent_id.to_string();
+    match kind {
+        "output" => Some(AgentResource::Output { agent_id, filter }),
+        "summary" => Some(AgentResource::Summary { agent_id }),
+        "metadata" => Some(AgentResource::Metadata { agent_id }),
+        "transcript" => Some(AgentResource::Transcript {
+            agent_id,
+            from,
+            limit,
+            filter,
+        }),
+        _ => None,
+    }
+}
+
+// Resources whose contents change with an agent event
+fn event_uris(ev: &AgentEvent) -> Vec<String> {
+    match ev.kind {
+        AgentEventKind::Output => vec![
+            agent_uri(&ev.agent_id, "output"),
+            agent_uri(&ev.agent_id, "transcript"),
+        ],
+        AgentEventKind::Status | AgentEventKind::Created | AgentEventKind::Removed => {
+            vec![AGENTS_URI.to_string(), agent_uri(&ev.agent_id, "metadata")]
+        }
+        AgentEventKind::Summary => vec![agent_uri(&ev.agent_id, "summary")],
+    }
+}
+
+// JSON-RPC ids may be numbers or strings; key them by their encoding so 1 and "1" differ
+fn request_key(id: &Value) -> String {
+    id.to_string()
+}
+
+// Structured tool output, mirrored as serialized JSON text for clients that only read content
+fn tool_result(v: Value) -> Value {
+    json!({
+        "content": [{"type": "text", "text": v.to_string()}],
+        "structuredContent": v,
+        "isError": false
+    })
+}
+
+fn error_data(e: &anyhow::Error) -> ErrorData {
+    if let Some(e) = e.downcast_ref::<AgentError>() {
+        e.data()
+    } else if let Some(e) = e.downcast_ref::<SummarizeError>() {
+        e.data()
+    } else if e.is::<serde_json::Error>() {
+        ErrorData {
+            code: "invalid_arguments",
+            message: e.to_string(),
+            retryable: false,
+            hint: Some("check the arguments against the tool's inputSchema"),
+        }
+    } else {
+        ErrorData {
+            code: "internal",
+            message: e.to_string(),
+            retryable: false,
+            hint: None,
+        }
+    }
+}
+
+// The error travels only as text: structuredContent must match the tool's outputSchema, which
+// describes successful results
+fn tool_error(data: ErrorData) -> Value {
+    json!({
+        "content": [{"type": "text", "text": json!({"error": data}).to_string()}],
+        "isError": true
+    })
+}
+
+fn notification(method: &str, params: Value) -> Value {
+    json!({"jsonrpc": "2.0", "method": method, "params": params})
+}
+
+// Turn agent events into resource notifications. Events are coalesced for `debounce`, so a
+// chatty agent produces at most one update per subscribed resource per window.
+async fn forward_notifications(
+    manager: Arc<AgentManagerImpl>,
+    session: Arc<Session>,
+    out: mpsc::UnboundedSender<Value>,
+    debounce: Duration,
+) {
+    let mut events = manager.subscribe_events();
+    let mut logs = logging::subscribe_logs();
+    let mut dirty: HashSet<String> = HashSet::new();
+    let mut list_changed = false;
+    let mut deadline: Option<tokio::time::Instant> = None;
+    loop {
+        let flush_at = deadline;
+        tokio::select! {
+            ev = events.recv() => {
+                match ev {
+                    Ok(ev) => {
+                        list_changed |=
+                            matches!(ev.kind, AgentEventKind::Created | AgentEventKind::Removed);
+                        dirty.extend(event_uris(&ev));
+                    }
+                    Err(RecvError::Lagged(_)) => {
+                        // Events were dropped; assume everything changed
+                        let subscribed = session.subscriptions.lock();
+                        dirty.extend(subscribed.iter().map(|u| base_uri(u).to_string()));
+                        list_changed = true;
+                    }
+                    Err(RecvError::Closed) => break,
+                }
+                deadline.get_or_insert_with(|| tokio::time::Instant::now() + debounce);
+            }
+            rec = logs.recv() => {
+                // Log lines missed while lagging are gone; the stderr log still has them
+                let Ok(rec) = rec else { continue };
+                if session.is_ready() && rec.level >= *session.log_level.lock() {
+                    let _ = out.send(notification("notifications/message", rec.to_params()));
+                }
+            }
+            _ = tokio::time::sleep_until(flush_at.unwrap_or_else(tokio::time::Instant::now)), if flush_at.is_some() => {
+                deadline = None;
+                let dirty = std::mem::take(&mut dirty);
+                let list_changed = std::mem::take(&mut list_changed);
+                if !session.is_ready() {
+                    continue;
+                }
+                let updated: Vec<String> = session
+                    .subscriptions
+                    .lock()
+                    .iter()
+                    .filter(|u| dirty.contains(base_uri(u)))
+                    .cloned()
+                    .collect();
+                for uri in updated {
+                    let _ = out.send(notification(
+                        "notifications/resources/updated",
+                        json!({"uri": uri}),
+                    ));
+                }
+                if list_changed {
+                    let _ = out.send(notification("notifications/resources/list_changed", json!({})));
+                }
+                if out.is_closed() {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+fn output_record_schema() -> Value {
+    json!({"type":"object","required":["seq","ts","stream","text"],"properties":{
+        "seq": {"type":"number"},
+        "ts": {"type":"string","description":"RFC 3339 timestamp."},
+        "stream": {"type":"string","enum":["stdout","stderr"]},
+        "text": {"type":"string","description":"The line as a terminal shows it: escape sequences stripped, carriage-return overwrites applied"},
+        "raw": {"type":"string","description":"Exactly what the agent wrote, when it differs from `text` and the agent keeps raw output"}
+    }})
+}
+
+// Timestamps here use time's default serde encoding, so their type is left open
+fn agent_info_schema() -> Value {
+    json!({"type":"object","required":["agent_id","pid","status","restart_count","crash_loop"],"properties":{
+        "agent_id": {"type":"string"},
+        "name": {"type":["string","null"]},
+        "pid": {"type":"number"},
+        "created_at": {},
+        "last_used": {},
+        "idle_reap_exempt": {"type":"boolean"},
+        "limits": {"type":"object"},
+        "status": {"type":"string","enum":["starting","running","exited","signaled","restarting","paused","stopped"]},
+        "exit_code": {"type":["number","null"]},
+        "exit_signal": {"type":["number","null"]},
+        "exited_at": {},
+        "restart_policy": {"type":"object"},
+        "restart_count": {"type":"number"},
+        "crash_loop": {"type":"boolean"},
+        "transcript_path": {"type":["string","null"]},
+        "pty": {"type":["object","null"],"description":"Window size (`rows`, `cols`) of agents running under a pseudo-terminal"}
+    }})
+}
+
+fn reap_record_schema() -> Value {
+    json!({"type":"object","required":["agent_id","idle_secs","reason"],"properties":{
+        "agent_id": {"type":"string"},
+        "name": {"type":["string","null"]},
+        "reaped_at": {},
+        "idle_secs": {"type":"number"},
+        "reason": {"type":"string"}
+    }})
+}
+
 fn list_tools_schema() -> Vec<serde_json::Value> {
     vec![
         json!({"name":"create_agent","description":"Create a persistent delegated cursor-agent subprocess to tackle a narrowly scoped subtask. Use when you want to delegate execution (CLI/REPL/server) separate from the main agent. Provide `working_dir` to scope filesystem context; pass `args` to configure the cursor-agent. Returns an `agent_id` you can use with other tools.","inputSchema":{"type":"object","properties":{
             "name": {"type":"string"},
             "working_dir": {"type":"string"},
             "env": {"type":"object","additionalProperties":{"type":"string"}},
-            "args": {"type":"array","items":{"type":"string"}}
+            "args": {"type":"array","items":{"type":"string"}},
+            "idle_reap_exempt": {"type":"boolean","description":"Keep this agent alive even when idle longer than IDLE_REAP_MINS."},
+            "limits": {"type":"object","description":"Optional rlimits for the agent process (unix only); values above the server's own hard limits are clamped.","properties":{
+                "cpu_secs": {"type":"number","description":"CPU seconds before the process is killed (RLIMIT_CPU)"},
+                "address_space_bytes": {"type":"number","description":"Maximum virtual memory in bytes (RLIMIT_AS)"},
+                "open_files": {"type":"number","description":"Maximum open file descriptors (RLIMIT_NOFILE)"},
+                "max_processes": {"type":"number","description":"Maximum processes for the user (RLIMIT_NPROC)"},
+                "core_bytes": {"type":"number","description":"Maximum core dump size in bytes (RLIMIT_CORE)"}
+            }},
+            "restart_policy": {"type":"object","description":"Respawn the agent automatically when its process exits on its own. Restarts keep buffered output. After `max_retries` consecutive quick failures the agent is flagged `crash_loop` and left down.","properties":{
+                "mode": {"type":"string","enum":["never","on_failure","always"]},
+                "max_retries": {"type":"number"},
+                "initial_backoff_ms": {"type":"number"},
+                "max_backoff_ms": {"type":"number"}
+            }},
+            "transcript": {"type":"boolean","description":"Write the full output to a JSONL transcript on disk (default: on when the server has TRANSCRIPT_DIR set). Read it back with `get_agent_transcript`."},
+            "pty": {"type":"boolean","description":"Run the agent under a pseudo-terminal instead of pipes (unix only), for programs that only stream output or behave interactively on a TTY. Output, stderr included, arrives as `stdout` with terminal echo of your input; `close_agent_stdin` sends Ctrl-D. Default false."},
+            "pty_size": {"type":"object","description":"Window size of the pseudo-terminal (default 24x80); change it later with `resize_agent_pty`. Only valid with `pty: true`; sent without it, the call fails with `invalid_arguments`.","properties":{
+                "rows": {"type":"number"},
+                "cols": {"type":"number"}
+            }},
+            "keep_raw_output": {"type":"boolean","description":"Output is normalized (ANSI colors and other escape sequences stripped, `\\r` progress overwrites collapsed to the final line). Set to also keep each rewritten line's original form as `raw` in output records; it counts against the buffer size. Transcripts always keep it. Default false."}
+        }},"outputSchema":{"type":"object","required":["agent_id","pid"],"properties":{
+            "agent_id": {"type":"string"},
+            "pid": {"type":"number"}
         }}}),
-        json!({"name":"send_agent_input","description":"Send one line of input to the delegated subagent's stdin (a trailing newline is added automatically). Use this to issue shell commands, REPL statements, or app-specific inputs after `create_agent`. For reading progress, use `get_agent_progress` rather than echoing stdout directly.","inputSchema": {"type":"object","required":["agent_id","input"],"properties":{
+        json!({"name":"send_agent_input","description":"Send one line of input to the delegated subagent's stdin (a trailing newline is added automatically). Use this to issue shell commands, REPL statements, or app-specific inputs after `create_agent`. Set `newline` to false to send partial input or keystrokes, and `escapes` to true to send control characters and raw bytes written as escapes (`\\n`, `\\r`, `\\t`, `\\e`, `\\0`, `\\xHH`, `\\\\`), e.g. `\\x03` for Ctrl-C or `\\x04` for Ctrl-D. For reading progress, use `get_agent_progress` rather than echoing stdout directly.","inputSchema": {"type":"object","required":["agent_id","input"],"properties":{
             "agent_id":{"type":"string"},
-            "input":{"type":"string"}
+            "input":{"type":"string"},
+            "newline":{"type":"boolean","description":"Append a newline (default true)."},
+            "escapes":{"type":"boolean","description":"Decode backslash escapes in `input` before sending (default false)."}
+        }},"outputSchema":{"type":"object","required":["accepted","bytes"],"properties":{
+            "accepted": {"type":"boolean"},
+            "bytes": {"type":"number"}
         }}}),
-        json!({"name":"get_agent_progress","description":"Summarize the subagent's buffered stdout/stderr without clearing it. Use to obtain concise snapshots of what the delegated subagent has done so far. Optionally pass `instructions` to steer summarization (e.g., 'focus on errors or TODOs'), and `max_tokens` to cap length. Use `reset_agent` to clear noise when needed.","inputSchema": {"type":"object","required":["agent_id"],"properties":{
+        json!({"name":"signal_agent","description":"Send a signal to the agent's whole process group: `SIGINT` interrupts a runaway command, `SIGTERM`/`SIGHUP` ask it to quit or reload, `SIGUSR1` is application-defined, `SIGSTOP` pauses the agent (status `paused`) and `SIGCONT` resumes it. The agent stays managed; use `stop_agent` to remove it.","inputSchema": {"type":"object","required":["agent_id","signal"],"properties":{
             "agent_id":{"type":"string"},
-            "instructions":{"type":"string"},
-            "max_tokens":{"type":"number"}
+            "signal":{"type":"string","enum":["SIGINT","SIGTERM","SIGHUP","SIGSTOP","SIGCONT","SIGUSR1"]}
+        }},"outputSchema":{"type":"object","required":["agent_id","signal","status"],"properties":{
+            "agent_id": {"type":"string"},
+            "signal": {"type":"string"},
+            "status": {"type":"string"}
+        }}}),
+        json!({"name":"close_agent_stdin","description":"Close the agent's stdin so it reads end-of-input, e.g. to finish a command that reads until EOF. Later input fails until a hard `reset_agent` (or an automatic restart) starts a new process.","inputSchema": {"type":"object","required":["agent_id"],"properties":{
+            "agent_id":{"type":"string"}
+        }},"outputSchema":{"type":"object","required":["closed","already_closed"],"properties":{
+            "closed": {"type":"boolean"},
+            "already_closed": {"type":"boolean"}
         }}}),
-        json!({"name":"reset_agent","description":"Reset the subagent state. Soft reset (default) clears the output buffer only (process keeps running). Hard reset (`hard=true`) restarts the underlying process with the same args/env/working_dir under the same ID. Use soft to remove noise; use hard if the process is wedged or needs a clean start.","inputSchema": {"type":"object","required":["agent_id"],"properties":{
+        json!({"name":"resize_agent_pty","description":"Change the window size of an agent created with `pty: true`. The agent receives SIGWINCH, and restarts keep the new size.","inputSchema": {"type":"object","required":["agent_id","rows","cols"],"properties":{
+            "agent_id":{"type":"string"},
+            "rows":{"type":"number"},
+            "cols":{"type":"number"}
+        }},"outputSchema":{"type":"object","required":["agent_id","rows","cols"],"properties":{
+            "agent_id": {"type":"string"},
+            "rows": {"type":"number"},
+            "cols": {"type":"number"}
+        }}}),
+        json!({"name":"get_agent_progress","description":"Summarize the subagent's buffered stdout/stderr without clearing it. Use to obtain concise snapshots of what the delegated subagent has done so far. Optionally pass `instructions` to steer summarization (e.g., 'focus on errors or TODOs'), and `max_tokens` to cap length. Narrow the input with `stream` and a `since`/`until` time window. Use `reset_agent` to clear noise when needed.","inputSchema": {"type":"object","required":["agent_id"],"properties":{
+            "agent_id":{"type":"string"},
+            "instructions":{"type":"string"},
+            "max_tokens":{"type":"number"},
+            "stream":{"type":"string","enum":["stdout","stderr"],"description":"Only lines from this stream."},
+            "since":{"type":"string","description":"Only lines at or after this RFC 3339 timestamp."},
+            "until":{"type":"string","description":"Only lines before this RFC 3339 timestamp."}
+        }},"outputSchema":{"type":"object","required":["summary","tokens_used","backend"],"properties":{
+            "summary": {"type":"string"},
+            "tokens_used": {"type":"number"},
+            "backend": {"type":"string","description":"Summarizer that produced the summary."}
+        }}}),
+        json!({"name":"reset_agent","description":"Reset the subagent state. Soft reset (default) clears the output buffer only (process keeps running). Hard reset (`hard=true`) kills the agent's process tree (reporting any `stragglers`) and restarts the underlying process with the same args/env/working_dir under the same ID. Use soft to remove noise; use hard if the process is wedged or needs a clean start.","inputSchema": {"type":"object","required":["agent_id"],"properties":{
             "agent_id":{"type":"string"},
             "hard":{"type":"boolean"}
+        }},"outputSchema":{"type":"object","required":["reset"],"properties":{
+            "reset": {"type":"string","enum":["soft","hard"]},
+            "stragglers": {"type":"array","items":{"type":"number"},"description":"Pids that outlived the old process and were killed (hard reset only)."}
         }}}),
-        json!({"name":"stop_agent","description":"Terminate and remove a delegated subagent when its subtask is complete. Defaults to a graceful termination; set `signal` to `kill` if the process does not exit in time.","inputSchema": {"type":"object","required":["agent_id"],"properties":{
+        json!({"name":"stop_agent","description":"Terminate and remove a delegated subagent when its subtask is complete. Defaults to a graceful termination: SIGTERM, then SIGKILL if the process has not exited after `grace_ms` (server default from STOP_GRACE_MS). Set `signal` to `kill` to skip the grace period. Signals the agent's whole process group, so shells and servers it spawned go down too. Reports the path taken (`terminated`, `escalated`, `killed`, `already_exited`), the final exit code/signal, and any `stragglers` (pids that outlived the agent and were killed).","inputSchema": {"type":"object","required":["agent_id"],"properties":{
             "agent_id":{"type":"string"},
-            "signal":{"type":"string","enum":["term","kill"]}
+            "signal":{"type":"string","enum":["term","kill"]},
+            "grace_ms":{"type":"number","description":"Milliseconds to wait after SIGTERM before escalating to SIGKILL."}
+        }},"outputSchema":{"type":"object","required":["stopped","path","stragglers"],"properties":{
+            "stopped": {"type":"boolean"},
+            "path": {"type":"string","enum":["already_exited","terminated","escalated","killed"]},
+            "exit_code": {"type":["number","null"]},
+            "exit_signal": {"type":["number","null"]},
+            "stragglers": {"type":"array","items":{"type":"number"}}
+        }}}),
+        json!({"name":"read_agent_output","description":"Read raw buffered output lines without summarizing, for cheap and deterministic tailing. Returns records (`seq`, `ts`, `stream`, `text`, plus `raw` when the agent keeps raw output) with `seq` >= `cursor` (oldest first) and `next_cursor` to pass on the following call; `seq` is monotonic per agent. Filter with `stream` and `since`/`until`; skipped lines still advance the cursor. `evicted` counts requested lines that already fell out of the buffer (see `get_agent_transcript` for older output). Start with `cursor` 0.","inputSchema": {"type":"object","required":["agent_id"],"properties":{
+            "agent_id":{"type":"string"},
+            "cursor":{"type":"number","description":"First sequence number to return (default 0)."},
+            "limit":{"type":"number","description":"Maximum lines to return (default 200)."},
+            "stream":{"type":"string","enum":["stdout","stderr"],"description":"Only lines from this stream."},
+            "since":{"type":"string","description":"Only lines at or after this RFC 3339 timestamp."},
+            "until":{"type":"string","description":"Only lines before this RFC 3339 timestamp."}
+        }},"outputSchema":{"type":"object","required":["records","next_cursor","evicted","has_more"],"properties":{
+            "records": {"type":"array","items": output_record_schema()},
+            "next_cursor": {"type":"number"},
+            "evicted": {"type":"number"},
+            "has_more": {"type":"boolean"}
+        }}}),
+        json!({"name":"get_agent_transcript","description":"Read an agent's full output transcript back from disk, including lines that have already scrolled out of the in-memory buffer used by `get_agent_progress`. Returns records (`seq`, `ts`, `stream`, `text`, `raw` for lines changed by normalization, same numbering as `read_agent_output`) with `seq` >= `from` that match the optional `stream`/`since`/`until` filter, oldest first, plus `next` to pass as `from` on the following call. `truncated` is set when the requested lines were already rotated away. Works for stopped agents while their files remain. Requires TRANSCRIPT_DIR on the server.","inputSchema": {"type":"object","required":["agent_id"],"properties":{
+            "agent_id":{"type":"string"},
+            "from":{"type":"number","description":"First sequence number to return (default 0)."},
+            "limit":{"type":"number","description":"Maximum records to return (default 500, at most 5000)."},
+            "stream":{"type":"string","enum":["stdout","stderr"],"description":"Only lines from this stream."},
+            "since":{"type":"string","description":"Only lines at or after this RFC 3339 timestamp."},
+            "until":{"type":"string","description":"Only lines before this RFC 3339 timestamp."}
+        }},"outputSchema":{"type":"object","required":["agent_id","records","next","truncated","has_more"],"properties":{
+            "agent_id": {"type":"string"},
+            "records": {"type":"array","items": output_record_schema()},
+            "next": {"type":"number"},
+            "first_available": {"type":["number","null"]},
+            "truncated": {"type":"boolean"},
+            "has_more": {"type":"boolean"}
+        }}}),
+        json!({"name":"list_agents","description":"List currently running delegated subagents, including `agent_id`, `pid`, `created_at`, `last_used`, status (`starting`, `running`, `exited`, `signaled`, `restarting`, `paused`, `stopped`) with the last exit code/signal/time, restart count and crash-loop flag, plus agents recently stopped by the idle reaper and why. Use to discover existing sessions to reuse instead of spawning a new one.","inputSchema": {"type":"object","properties":{}},"outputSchema":{"type":"object","required":["agents","reaped"],"properties":{
+            "agents": {"type":"array","items": agent_info_schema()},
+            "reaped": {"type":"array","items": reap_record_schema()}
         }}}),
-        json!({"name":"list_agents","description":"List currently running delegated subagents, including `agent_id`, `pid`, `created_at`, and status. Use to discover existing sessions to reuse instead of spawning a new one.","inputSchema": {"type":"object","properties":{}}}),
         json!({"name":"wait","description":"Sleep for a duration. Useful to briefly yield while background commands produce output before calling `get_agent_progress`. Prefer short waits and poll rather than long sleeps.","inputSchema": {"type":"object","properties":{
             "ms": {"type":"number"},
             "seconds": {"type":"number"}
+        }},"outputSchema":{"type":"object","required":["waited_ms"],"properties":{
+            "waited_ms": {"type":"number"}
+        }}}),
+        json!({"name":"wait_for_output","description":"Block until the agent's output matches `pattern`, the agent has been quiet for `idle_ms`, or (with `until_exit`) its process exits, whichever comes first, or until `timeout_ms` passes. Use instead of `wait` plus polling after sending input. `reason` says why it returned (`matched`, `idle`, `exited`, `timeout`) and `matches` holds the matching lines. By default only lines written after the call are examined; pass `cursor` (e.g. the `next_cursor` of an earlier read or wait) to also catch output produced in between. Narrow the lines searched with `stream` and `since`/`until`.","inputSchema": {"type":"object","required":["agent_id"],"properties":{
+            "agent_id":{"type":"string"},
+            "pattern":{"type":"string","description":"Regular expression matched against each output line."},
+            "idle_ms":{"type":"number","description":"Return once no output has arrived for this many milliseconds."},
+            "until_exit":{"type":"boolean","description":"Return when the agent's process exits."},
+            "timeout_ms":{"type":"number","description":"Give up after this many milliseconds (default 30000)."},
+            "cursor":{"type":"number","description":"First sequence number to search (default: the next line written)."},
+            "stream":{"type":"string","enum":["stdout","stderr"],"description":"Only match lines from this stream."},
+            "since":{"type":"string","description":"Only match lines at or after this RFC 3339 timestamp."},
+            "until":{"type":"string","description":"Only match lines before this RFC 3339 timestamp."}
+        }},"outputSchema":{"type":"object","required":["reason","matches","next_cursor","status","waited_ms"],"properties":{
+            "reason": {"type":"string","enum":["matched","idle","exited","timeout"]},
+            "matches": {"type":"array","items": output_record_schema()},
+            "next_cursor": {"type":"number"},
+            "status": {"type":"string"},
+            "waited_ms": {"type":"number"}
+        }}}),
+        json!({"name":"send_and_expect","description":"Script an interactive session: for each step, send a line of input (optional) and wait for the first output line matching any of its `expect` regexes, within the step's `timeout_ms` (default 30000). Replaces chains of `send_agent_input`, `wait` and reads. The script runs atomically: other scripts and `send_agent_input` calls for the same agent wait until it finishes. It stops at the first step that times out, sees the process exit, or cannot write its input (`send_failed`, with the cause in `error`). Each step reports which pattern matched (`matched` index and `pattern`) and the `output` captured from its send up to and including the matching line; output after a match is left for the next step. `completed` is true when every step matched.","inputSchema": {"type":"object","required":["agent_id","steps"],"properties":{
+            "agent_id":{"type":"string"},
+            "steps":{"type":"array","minItems":1,"items":{"type":"object","required":["expect"],"properties":{
+                "send":{"type":"string","description":"Line written to stdin before waiting (newline added)."},
+                "expect":{"type":"array","minItems":1,"items":{"type":"string"},"description":"Regular expressions; the first line matching any of them completes the step."},
+                "timeout_ms":{"type":"number","description":"How long this step may wait (default 30000)."}
+            }}}
+        }},"outputSchema":{"type":"object","required":["completed","steps","next_cursor","status"],"properties":{
+            "completed": {"type":"boolean"},
+            "steps": {"type":"array","items":{"type":"object","required":["reason","output","waited_ms"],"properties":{
+                "reason": {"type":"string","enum":["matched","exited","timeout","send_failed"]},
+                "matched": {"type":["number","null"]},
+                "pattern": {"type":["string","null"]},
+                "output": {"type":"array","items": output_record_schema()},
+                "waited_ms": {"type":"number"},
+                "error": {"type":"string"}
+            }}},
+            "next_cursor": {"type":"number"},
+            "status": {"type":"string"}
+        }}}),
+        json!({"name":"metrics","description":"Return server-side metrics: created/stopped/reaped counts, recent idle reaps, active sessions, and I/O byte totals. Use for monitoring and debugging.","inputSchema": {"type":"object","properties":{}},"outputSchema":{"type":"object","required":["created_count","stopped_count","reaped_count","active_count","total_input_bytes","total_output_bytes","cancelled_count","recently_reaped"],"properties":{
+            "created_count": {"type":"number"},
+            "stopped_count": {"type":"number"},
+            "reaped_count": {"type":"number"},
+            "active_count": {"type":"number"},
+            "total_input_bytes": {"type":"number"},
+            "total_output_bytes": {"type":"number"},
+            "cancelled_count": {"type":"number"},
+            "recently_reaped": {"type":"array","items": reap_record_schema()}
+        }}}),
+        json!({"name":"health_check","description":"Run dependency checks: verifies `cursor-agent` availability, Ollama connectivity (if configured), and llama.cpp CLI presence. Use to diagnose environment issues before delegating.","inputSchema": {"type":"object","properties":{}},"outputSchema":{"type":"object","required":["cursor_agent_ok","ollama_ok","llama_cpp_ok","server"],"properties":{
+            "cursor_agent_ok": {"type":"boolean"},
+            "ollama_ok": {"type":"boolean"},
+            "llama_cpp_ok": {"type":"boolean"},
+            "server": {"type":"object","properties":{"name":{"type":"string"},"version":{"type":"string"}}}
         }}}),
-        json!({"name":"metrics","description":"Return server-side metrics: created/stopped counts, active sessions, and I/O byte totals. Use for monitoring and debugging.","inputSchema": {"type":"object","properties":{}}}),
-        json!({"name":"health_check","description":"Run dependency checks: verifies `cursor-agent` availability, Ollama connectivity (if configured), and llama.cpp CLI presence. Use to diagnose environment issues before delegating.","inputSchema": {"type":"object","properties":{}}}),
     ]
 }
 
-fn read_framed_message_buf<R: std::io::BufRead>(bufreader: &mut R) -> anyhow::Result<Vec<u8>> {
+/// Wire format of one connection. Starts as Content-Length framing and switches to ND-JSON (one
+/// JSON message per line, no headers) once the client is seen sending raw JSON lines.
+#[derive(Clone)]
+struct Framing {
+    ndjson: Arc<AtomicBool>,
+    max_bytes: usize,
+}
+
+impl Default for Framing {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_MESSAGE_BYTES)
+    }
+}
+
+/// A message over the size limit. Its bytes have been consumed, so reading can continue.
+#[derive(Debug, thiserror::Error)]
+#[error("Invalid Request: message of {size} bytes exceeds the {limit} byte limit")]
+struct FrameTooLarge {
+    size: usize,
+    limit: usize,
+}
+
+/// Headers without a usable Content-Length. They have been consumed up to the blank line that
+/// ends them, so reading can continue.
+#[derive(Debug, thiserror::Error)]
+#[error("Invalid Request: {0}")]
+struct InvalidFrame(String);
+
+impl Framing {
+    fn new(max_bytes: usize) -> Self {
+        Self {
+            ndjson: Arc::default(),
+            max_bytes,
+        }
+    }
+
+    fn is_ndjson(&self) -> bool {
+        // MCP_FORCE_NDJSON=1 replies in ND-JSON from the start
+        self.ndjson.load(Ordering::Relaxed)
+            || std::env::var("MCP_FORCE_NDJSON").ok().as_deref() == Some("1")
+    }
+}
+
+fn read_framed_message_buf<R: std::io::BufRead>(
+    bufreader: &mut R,
+    framing: &Framing,
+) -> anyhow::Result<Vec<u8>> {
     let mut header = String::new();
-    let mut content_length: Option<usize> = None;
+    let mut content_length: Option<Result<usize, String>> = None;
     let mut header_lines: usize = 0;
     loop {
         header.clear();
-        let n = bufreader.read_line(&mut header)?;
+        let n = read_line_limited(bufreader, &mut header, framing.max_bytes)?;
         if n == 0 {
             anyhow::bail!("eof");
         }
@@ -384,22 +1568,36 @@ fn read_framed_message_buf<R: std::io::BufRead>(bufreader: &mut R) -> anyhow::Re
         }
         header_lines += 1;
         tracing::trace!(%line, "framing header line");
-        // Fallback for clients that send newline-delimited raw JSON instead of framed headers
-        if header_lines == 1 && line.starts_with('{') && line.contains("\"jsonrpc\"") {
+        // Fallback for clients that send newline-delimited raw JSON (objects or batches) instead
+        // of framed headers
+        if header_lines == 1 && (line.starts_with('{') || line.starts_with('[')) {
             tracing::debug!("detected raw JSON line without Content-Length; accepting as body");
-            RAW_JSON_MODE.store(true, Ordering::Relaxed);
+            framing.ndjson.store(true, Ordering::Relaxed);
             return Ok(line.as_bytes().to_vec());
         }
         if let Some((name, value)) = line.split_once(':') {
             let name = name.trim();
             if name.eq_ignore_ascii_case("content-length") {
                 let v = value.trim();
-                content_length = Some(v.parse::<usize>()?);
+                // Keep reading to the blank line either way so the next frame starts in sync
+                content_length = Some(v.parse::<usize>().map_err(|_| v.to_string()));
             }
             // ignore other headers (e.g., Content-Type)
         }
     }
-    let len = content_length.ok_or_else(|| anyhow::anyhow!("missing Content-Length"))?;
+    let len = match content_length {
+        Some(Ok(len)) => len,
+        Some(Err(v)) => return Err(InvalidFrame(format!("bad Content-Length {v:?}")).into()),
+        None => return Err(InvalidFrame("missing Content-Length".into()).into()),
+    };
+    if len > framing.max_bytes {
+        std::io::copy(&mut bufreader.take(len as u64), &mut std::io::sink())?;
+        return Err(FrameTooLarge {
+            size: len,
+            limit: framing.max_bytes,
+        }
+        .into());
+    }
     let mut body = vec![0u8; len];
     bufreader.read_exact(&mut body)?;
     let preview = std::str::from_utf8(&body)
@@ -410,31 +1608,48 @@ fn read_framed_message_buf<R: std::io::BufRead>(bufreader: &mut R) -> anyhow::Re
     Ok(body)
 }
 
-fn write_response<W: Write>(
-    writer: &mut W,
-    id: serde_json::Value,
-    result: serde_json::Value,
-) -> anyhow::Result<()> {
-    let resp = json!({"jsonrpc":"2.0","id": id, "result": result});
-    write_framed(writer, &resp)
+// Read one line of at most `max` bytes (plus terminator). A longer line is consumed to its end and
+// reported as FrameTooLarge.
+fn read_line_limited<R: std::io::BufRead>(
+    reader: &mut R,
+    buf: &mut String,
+    max: usize,
+) -> anyhow::Result<usize> {
+    let n = std::io::BufRead::read_line(&mut reader.by_ref().take(max as u64 + 2), buf)?;
+    let content = buf.trim_end_matches(['\r', '\n']).len();
+    if content <= max {
+        return Ok(n);
+    }
+    let mut size = content;
+    if !buf.ends_with('\n') {
+        loop {
+            let chunk = reader.fill_buf()?;
+            if chunk.is_empty() {
+                break;
+            }
+            if let Some(pos) = chunk.iter().position(|b| *b == b'\n') {
+                reader.consume(pos + 1);
+                size += pos;
+                break;
+            }
+            let len = chunk.len();
+            reader.consume(len);
+            size += len;
+        }
+    }
+    buf.clear();
+    Err(FrameTooLarge { size, limit: max }.into())
 }
 
-fn write_error<W: Write>(
+fn write_framed<W: Write>(
     writer: &mut W,
-    id: serde_json::Value,
-    code: i64,
-    message: &str,
+    v: &serde_json::Value,
+    framing: &Framing,
 ) -> anyhow::Result<()> {
-    let resp = json!({"jsonrpc":"2.0","id": id, "error": {"code": code, "message": message}});
-    write_framed(writer, &resp)
-}
-
-fn write_framed<W: Write>(writer: &mut W, v: &serde_json::Value) -> anyhow::Result<()> {
     let s = serde_json::to_string(v)?;
     // Respond in ND-JSON mode if detected (or forced), otherwise use Content-Length framing.
     // This maximizes compatibility with editors that do not send LSP-style headers over stdio.
-    let force_ndjson = std::env::var("MCP_FORCE_NDJSON").ok().as_deref() == Some("1");
-    if force_ndjson || RAW_JSON_MODE.load(Ordering::Relaxed) {
+    if framing.is_ndjson() {
         writeln!(writer, "{}", s)?;
     } else {
         write!(writer, "Content-Length: {}\r\n\r\n{}", s.len(), s)?;
@@ -447,6 +1662,7 @@ fn write_framed<W: Write>(writer: &mut W, v: &serde_json::Value) -> anyhow::Resu
 mod tests {
     use super::*;
     // no extra imports needed here
+    use crate::agents::model::OutputStream;
     use std::sync::Arc;
 
     struct DummySummarizer;
@@ -465,19 +1681,875 @@ mod tests {
         }
     }
 
+    // Blocks until cancelled, recording that the token reached the blocking call
+    #[derive(Default)]
+    struct StuckSummarizer {
+        cancelled: Arc<AtomicBool>,
+    }
+    impl Summarizer for StuckSummarizer {
+        fn summarize(
+            &self,
+            _context: &str,
+            _instructions: Option<&str>,
+            _max_tokens: usize,
+        ) -> Result<crate::summarize::SummarizeResult, crate::errors::SummarizeError> {
+            unreachable!("the server calls summarize_cancellable")
+        }
+
+        fn summarize_cancellable(
+            &self,
+            _context: &str,
+            _instructions: Option<&str>,
+            _max_tokens: usize,
+            cancel: &CancellationToken,
+        ) -> Result<crate::summarize::SummarizeResult, crate::errors::SummarizeError> {
+            while !cancel.is_cancelled() {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            self.cancelled.store(true, Ordering::SeqCst);
+            Err(crate::errors::SummarizeError::Cancelled)
+        }
+    }
+
+    #[test]
+    fn input_escapes_decode_to_raw_bytes() {
+        assert_eq!(
+            decode_escapes(r"ls\tx\r\n\x03\e[A\0\\é").unwrap(),
+            b"ls\tx\r\n\x03\x1b[A\0\\\xc3\xa9".to_vec()
+        );
+        assert!(decode_escapes(r"\x4").is_err());
+        assert!(decode_escapes(r"\x+f").is_err());
+        assert!(decode_escapes(r"\x-1").is_err());
+        assert_eq!(decode_escapes(r"\xfF").unwrap(), vec![0xff]);
+        assert!(decode_escapes(r"\q").is_err());
+        assert!(decode_escapes("trailing\\").is_err());
+    }
+
     #[test]
     fn framed_write_and_read_roundtrip() {
         let v = serde_json::json!({"jsonrpc":"2.0","id":1,"result":{"ok":true}});
         let mut out = Vec::new();
-        write_framed(&mut out, &v).expect("write");
+        let framing = Framing::default();
+        write_framed(&mut out, &v, &framing).expect("write");
         let mut cursor = std::io::Cursor::new(out);
         // read via helper
         let mut bufreader = std::io::BufReader::new(&mut cursor);
-        let body = read_framed_message_buf(&mut bufreader).expect("read");
+        let body = read_framed_message_buf(&mut bufreader, &framing).expect("read");
         let parsed: serde_json::Value = serde_json::from_slice(&body).unwrap();
         assert_eq!(parsed, v);
     }
 
+    #[test]
+    fn raw_json_framing_is_detected_per_connection() {
+        let raw = Framing::default();
+        let framed = Framing::default();
+        let line = b"{\"jsonrpc\":\"2.0\",\"id\":1,\"method\":\"tools/list\"}\n";
+        let mut reader = std::io::BufReader::new(&line[..]);
+        read_framed_message_buf(&mut reader, &raw).expect("read");
+        assert!(raw.is_ndjson());
+
+        let v = json!({"jsonrpc":"2.0","id":1,"result":{}});
+        let mut out = Vec::new();
+        write_framed(&mut out, &v, &raw).unwrap();
+        assert!(out.starts_with(b"{"));
+        let mut out = Vec::new();
+        write_framed(&mut out, &v, &framed).unwrap();
+        assert!(out.starts_with(b"Content-Length:"));
+    }
+
+    #[test]
+    fn oversized_frames_are_rejected_without_losing_sync() {
+        let framing = Framing::new(64);
+        let big = format!("{{\"jsonrpc\":\"2.0\",\"method\":\"{}\"}}", "x".repeat(100));
+        let small = "{\"jsonrpc\":\"2.0\",\"id\":1,\"method\":\"ping\"}";
+        let input = format!(
+            "Content-Length: {}\r\n\r\n{big}Content-Length: {}\r\n\r\n{small}",
+            big.len(),
+            small.len()
+        );
+        let mut reader = std::io::BufReader::new(input.as_bytes());
+        let err = read_framed_message_buf(&mut reader, &framing).unwrap_err();
+        assert!(err.is::<FrameTooLarge>());
+        let body = read_framed_message_buf(&mut reader, &framing).expect("read");
+        assert_eq!(body, small.as_bytes());
+
+        // Raw JSON lines, including batches, are bounded the same way
+        let raw = Framing::new(64);
+        let input = format!("{big}\n[{small}]\n");
+        let mut reader = std::io::BufReader::new(input.as_bytes());
+        let err = read_framed_message_buf(&mut reader, &raw).unwrap_err();
+        assert!(err.is::<FrameTooLarge>());
+        let body = read_framed_message_buf(&mut reader, &raw).expect("read");
+        assert_eq!(body, format!("[{small}]").as_bytes());
+        assert!(raw.is_ndjson());
+    }
+
+    #[test]
+    fn frames_without_a_usable_length_are_skipped() {
+        let framing = Framing::new(1024);
+        let small = "{\"jsonrpc\":\"2.0\",\"id\":1,\"method\":\"ping\"}";
+        let input = format!(
+            "Content-Length: lots\r\nContent-Type: application/json\r\n\r\n\
+             Content-Type: application/json\r\n\r\n\
+             Content-Length: {}\r\n\r\n{small}",
+            small.len()
+        );
+        let mut reader = std::io::BufReader::new(input.as_bytes());
+        let err = read_framed_message_buf(&mut reader, &framing).unwrap_err();
+        assert!(err.to_string().contains("bad Content-Length \"lots\""));
+        assert!(err.is::<InvalidFrame>());
+        let err = read_framed_message_buf(&mut reader, &framing).unwrap_err();
+        assert!(err.to_string().contains("missing Content-Length"));
+        assert!(err.is::<InvalidFrame>());
+        let body = read_framed_message_buf(&mut reader, &framing).expect("read");
+        assert_eq!(body, small.as_bytes());
+        assert!(!framing.is_ndjson());
+    }
+
+    #[tokio::test]
+    async fn batches_and_malformed_messages_get_json_rpc_errors() {
+        let manager = Arc::new(crate::agents::manager::AgentManagerImpl::new(None, 1024));
+        let server = McpServer::new(manager, Arc::new(DummySummarizer));
+        let (in_tx, in_rx) = mpsc::unbounded_channel::<Vec<u8>>();
+        let (out_tx, mut out_rx) = mpsc::unbounded_channel();
+        let serving = tokio::spawn({
+            let server = server.clone();
+            async move { server.serve(in_rx, out_tx).await }
+        });
+        async fn exchange(
+            tx: &mpsc::UnboundedSender<Vec<u8>>,
+            rx: &mut mpsc::UnboundedReceiver<Value>,
+            msg: &str,
+        ) -> Value {
+            tx.send(msg.as_bytes().to_vec()).unwrap();
+            next_response(rx).await
+        }
+
+        let resp = exchange(&in_tx, &mut out_rx, "{not json").await;
+        assert_eq!(resp["error"]["code"], -32700);
+        assert!(resp["id"].is_null());
+        let resp = exchange(
+            &in_tx,
+            &mut out_rx,
+            r#"{"jsonrpc":"1.0","id":7,"method":"ping"}"#,
+        )
+        .await;
+        assert_eq!(resp["error"]["code"], -32600);
+        assert_eq!(resp["id"], 7);
+        let resp = exchange(
+            &in_tx,
+            &mut out_rx,
+            r#"{"jsonrpc":"2.0","id":[1],"method":"ping"}"#,
+        )
+        .await;
+        assert_eq!(resp["error"]["code"], -32600);
+        let resp = exchange(&in_tx, &mut out_rx, "[]").await;
+        assert_eq!(resp["error"]["code"], -32600);
+
+        let init = json!({"jsonrpc":"2.0","id":0,"method":"initialize","params":{}});
+        assert!(exchange(&in_tx, &mut out_rx, &init.to_string()).await["result"].is_object());
+        // Initialize is refused inside a batch; notifications get no entry in the reply
+        let batch = json!([
+            {"jsonrpc":"2.0","id":"b","method":"tools/list"},
+            {"jsonrpc":"2.0","method":"notifications/initialized"},
+            7,
+            {"jsonrpc":"2.0","id":"a","method":"ping"},
+            init,
+        ]);
+        let resp = exchange(&in_tx, &mut out_rx, &batch.to_string()).await;
+        let resp = resp.as_array().expect("batch response");
+        assert_eq!(resp.len(), 4);
+        assert_eq!(resp[0]["id"], "b");
+        assert!(resp[0]["result"]["tools"].is_array());
+        assert_eq!(resp[1]["error"]["code"], -32600);
+        assert_eq!(resp[2]["id"], "a");
+        assert_eq!(resp[2]["result"], json!({}));
+        assert_eq!(resp[3]["error"]["code"], -32600);
+        assert!(server.session.is_ready());
+
+        // A batch of notifications only is answered with nothing at all
+        in_tx
+            .send(br#"[{"jsonrpc":"2.0","method":"notifications/initialized"}]"#.to_vec())
+            .unwrap();
+        let resp = exchange(
+            &in_tx,
+            &mut out_rx,
+            r#"{"jsonrpc":"2.0","id":9,"method":"ping"}"#,
+        )
+        .await;
+        assert_eq!(resp["id"], 9);
+        drop(in_tx);
+        serving.await.unwrap();
+    }
+
+    #[test]
+    fn agent_uri_parsing() {
+        assert_eq!(
+            parse_agent_uri("mcp://cursor-mcp-subagents/agents/abc/transcript?from=10&limit=5"),
+            Some(AgentResource::Transcript {
+                agent_id: "abc".into(),
+                from: 10,
+                limit: 5,
+                filter: OutputFilter::default()
+            })
+        );
+        assert_eq!(
+            parse_agent_uri("mcp://cursor-mcp-subagents/agents/abc/transcript"),
+            Some(AgentResource::Transcript {
+                agent_id: "abc".into(),
+                from: 0,
+                limit: DEFAULT_TRANSCRIPT_LIMIT,
+                filter: OutputFilter::default()
+            })
+        );
+        let Some(AgentResource::Output { agent_id, filter }) = parse_agent_uri(
+            "mcp://cursor-mcp-subagents/agents/abc/output?stream=stderr&since=2024-01-02T03:04:05Z",
+        ) else {
+            panic!("expected output resource");
+        };
+        assert_eq!(agent_id, "abc");
+        assert_eq!(filter.stream, Some(OutputStream::Stderr));
+        assert_eq!(
+            filter.since,
+            Some(time::macros::datetime!(2024-01-02 03:04:05 UTC))
+        );
+        assert!(
+            parse_agent_uri("mcp://cursor-mcp-subagents/agents/abc/output?stream=both").is_none()
+        );
+        assert!(parse_agent_uri("mcp://cursor-mcp-subagents/agents/abc/bogus").is_none());
+        assert!(
+            parse_agent_uri("mcp://cursor-mcp-subagents/agents/abc/transcript?from=x").is_none()
+        );
+        assert!(is_known_resource("mcp://cursor-mcp-subagents/agents"));
+        assert!(!is_known_resource("mcp://elsewhere/agents/abc/output"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn subscriptions_get_debounced_updates_and_list_changes() {
+        let manager = Arc::new(crate::agents::manager::AgentManagerImpl::new(
+            Some("/bin/cat".into()),
+            64 * 1024,
+        ));
+        let server = McpServer::new(manager.clone(), Arc::new(DummySummarizer))
+            .with_notify_debounce(Duration::from_millis(50));
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let task = tokio::spawn(forward_notifications(
+            manager.clone(),
+            server.session.clone(),
+            tx,
+            server.notify_debounce,
+        ));
+        let created = manager.create(CreateAgentRequest::default()).await.unwrap();
+        // Nothing is sent before the client finished initialization
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        assert!(rx.try_recv().is_err());
+
+        initialize(&server).await;
+        let output_uri = agent_uri(&created.agent_id, "output");
+        let resp = server
+            .handle_message(json!({"jsonrpc":"2.0","id":1,"method":"resources/subscribe","params":{"uri": output_uri}}))
+            .await
+            .unwrap();
+        assert!(resp.get("result").is_some());
+        for i in 0..20 {
+            manager
+                .send_input(&created.agent_id, &format!("line {i}"))
+                .await
+                .unwrap();
+        }
+        // Let every echo land and the last debounce window flush before counting
+        let all_echoed = WaitCondition {
+            pattern: Some(regex::Regex::new("^line 19$").unwrap()),
+            ..Default::default()
+        };
+        manager
+            .wait_for_output(
+                &created.agent_id,
+                Some(0),
+                &all_echoed,
+                Duration::from_secs(5),
+            )
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let mut updates = Vec::new();
+        while let Ok(msg) = rx.try_recv() {
+            updates.push(msg);
+        }
+        // Twenty lines coalesce into an update or two for the subscribed resource, depending on
+        // how many debounce windows the echoes span
+        assert!((1..=2).contains(&updates.len()), "{updates:?}");
+        for update in &updates {
+            assert_eq!(update["method"], "notifications/resources/updated");
+            assert_eq!(update["params"]["uri"], output_uri);
+        }
+
+        server
+            .handle_message(json!({"jsonrpc":"2.0","id":2,"method":"resources/unsubscribe","params":{"uri": output_uri}}))
+            .await;
+        manager
+            .stop(&created.agent_id, StopSignal::Kill, None)
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let msg = rx.try_recv().unwrap();
+        assert_eq!(msg["method"], "notifications/resources/list_changed");
+        assert!(rx.try_recv().is_err());
+        task.abort();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn per_agent_resources_resolve_through_manager() {
+        let manager = Arc::new(crate::agents::manager::AgentManagerImpl::new(
+            Some("/bin/cat".into()),
+            64 * 1024,
+        ));
+        let server = McpServer::new(manager.clone(), Arc::new(DummySummarizer));
+        initialize(&server).await;
+        let resp = server
+            .handle_message(json!({"jsonrpc":"2.0","id":1,"method":"resources/templates/list"}))
+            .await
+            .unwrap();
+        let templates = resp["result"]["resourceTemplates"].as_array().unwrap();
+        let uris: Vec<_> = templates
+            .iter()
+            .map(|t| t["uriTemplate"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            uris,
+            vec![
+                "mcp://cursor-mcp-subagents/agents/{agent_id}/output",
+                "mcp://cursor-mcp-subagents/agents/{agent_id}/summary",
+                "mcp://cursor-mcp-subagents/agents/{agent_id}/metadata",
+                "mcp://cursor-mcp-subagents/agents/{agent_id}/transcript",
+            ]
+        );
+
+        let created = server
+            .dispatch_tool(
+                "create_agent",
+                json!({"name": "r"}),
+                &CancellationToken::new(),
+            )
+            .await
+            .unwrap();
+        let id = created["agent_id"].as_str().unwrap().to_string();
+        let read = |kind: &str| json!({"jsonrpc":"2.0","id":2,"method":"resources/read","params":{"uri": agent_uri(&id, kind)}});
+        let text = |resp: Value| {
+            resp["result"]["contents"][0]["text"]
+                .as_str()
+                .unwrap()
+                .to_string()
+        };
+
+        let summary = text(server.handle_message(read("summary")).await.unwrap());
+        assert_eq!(summary, "null");
+        server
+            .dispatch_tool(
+                "send_agent_input",
+                json!({"agent_id": id, "input": "hello"}),
+                &CancellationToken::new(),
+            )
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(
+            text(server.handle_message(read("output")).await.unwrap()),
+            "hello\n"
+        );
+        server
+            .dispatch_tool(
+                "get_agent_progress",
+                json!({"agent_id": id}),
+                &CancellationToken::new(),
+            )
+            .await
+            .unwrap();
+        let summary: Value =
+            serde_json::from_str(&text(server.handle_message(read("summary")).await.unwrap()))
+                .unwrap();
+        assert_eq!(summary["backend"], "dummy");
+        assert_eq!(summary["summary"], "hello\n");
+        let meta: Value = serde_json::from_str(&text(
+            server.handle_message(read("metadata")).await.unwrap(),
+        ))
+        .unwrap();
+        assert_eq!(meta["agent_id"], id.as_str());
+        assert_eq!(meta["name"], "r");
+
+        // Without TRANSCRIPT_DIR the transcript is not listed and reading it fails
+        let resp = server
+            .handle_message(json!({"jsonrpc":"2.0","id":3,"method":"resources/list"}))
+            .await
+            .unwrap();
+        let listed: Vec<_> = resp["result"]["resources"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|r| r["uri"].as_str().unwrap().to_string())
+            .collect();
+        assert!(listed.contains(&agent_uri(&id, "metadata")));
+        assert!(!listed.contains(&agent_uri(&id, "transcript")));
+        let resp = server.handle_message(read("transcript")).await.unwrap();
+        assert!(resp.get("error").is_some());
+
+        manager.stop(&id, StopSignal::Kill, None).await.unwrap();
+        let resp = server.handle_message(read("metadata")).await.unwrap();
+        assert!(resp.get("error").is_some());
+    }
+
+    // Complete the initialize handshake so the session accepts requests and sends notifications
+    async fn initialize(server: &McpServer) {
+        server
+            .handle_message(json!({"jsonrpc":"2.0","id":0,"method":"initialize","params":{"protocolVersion":"2025-06-18"}}))
+            .await
+            .unwrap();
+        server
+            .handle_message(json!({"jsonrpc":"2.0","method":"notifications/initialized"}))
+            .await;
+    }
+
+    fn request(id: u64, method: &str, params: Value) -> Vec<u8> {
+        serde_json::to_vec(&json!({"jsonrpc":"2.0","id": id,"method": method,"params": params}))
+            .unwrap()
+    }
+
+    // Log records from tests running concurrently reach every ready session as notifications, so
+    // tests looking for responses skip anything without an id
+    async fn next_response(rx: &mut mpsc::UnboundedReceiver<Value>) -> Value {
+        loop {
+            let msg = tokio::time::timeout(Duration::from_secs(5), rx.recv())
+                .await
+                .expect("no response within 5s")
+                .expect("output closed");
+            // Batch responses are arrays
+            if msg.is_array() || msg.get("id").is_some() {
+                return msg;
+            }
+        }
+    }
+
+    fn assert_no_response(rx: &mut mpsc::UnboundedReceiver<Value>) {
+        let responses: Vec<_> = std::iter::from_fn(|| rx.try_recv().ok())
+            .filter(|msg| msg.is_array() || msg.get("id").is_some())
+            .collect();
+        assert!(responses.is_empty(), "unexpected responses: {responses:?}");
+    }
+
+    #[tokio::test]
+    async fn serve_answers_fast_requests_while_slow_ones_run() {
+        let manager = Arc::new(crate::agents::manager::AgentManagerImpl::new(None, 1024));
+        let server = McpServer::new(manager, Arc::new(DummySummarizer));
+        initialize(&server).await;
+        let (in_tx, in_rx) = mpsc::unbounded_channel();
+        let (out_tx, mut out_rx) = mpsc::unbounded_channel();
+        let serving = tokio::spawn(async move { server.serve(in_rx, out_tx).await });
+        in_tx
+            .send(request(
+                1,
+                "tools/call",
+                json!({"name":"wait","arguments":{"ms": 300}}),
+            ))
+            .unwrap();
+        in_tx
+            .send(request(
+                2,
+                "tools/call",
+                json!({"name":"list_agents","arguments":{}}),
+            ))
+            .unwrap();
+        let first = next_response(&mut out_rx).await;
+        assert_eq!(first["id"], 2);
+        let second = next_response(&mut out_rx).await;
+        assert_eq!(second["id"], 1);
+        drop(in_tx);
+        serving.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn serve_caps_concurrent_requests() {
+        let manager = Arc::new(crate::agents::manager::AgentManagerImpl::new(None, 1024));
+        let server = McpServer::new(manager, Arc::new(DummySummarizer)).with_max_concurrency(1);
+        initialize(&server).await;
+        let (in_tx, in_rx) = mpsc::unbounded_channel();
+        let (out_tx, mut out_rx) = mpsc::unbounded_channel();
+        let serving = tokio::spawn(async move { server.serve(in_rx, out_tx).await });
+        in_tx
+            .send(request(
+                1,
+                "tools/call",
+                json!({"name":"wait","arguments":{"ms": 200}}),
+            ))
+            .unwrap();
+        in_tx
+            .send(request(
+                2,
+                "tools/call",
+                json!({"name":"list_agents","arguments":{}}),
+            ))
+            .unwrap();
+        // With a single slot the second request waits for the first
+        assert_eq!(next_response(&mut out_rx).await["id"], 1);
+        assert_eq!(next_response(&mut out_rx).await["id"], 2);
+        drop(in_tx);
+        serving.await.unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn cancelled_requests_get_no_response_and_are_counted() {
+        let manager = Arc::new(crate::agents::manager::AgentManagerImpl::new(
+            Some("/bin/cat".into()),
+            1024,
+        ));
+        let summarizer = Arc::new(StuckSummarizer::default());
+        let cancelled = summarizer.cancelled.clone();
+        let server = McpServer::new(manager.clone(), summarizer);
+        initialize(&server).await;
+        let agent_id = server
+            .dispatch_tool("create_agent", json!({}), &CancellationToken::new())
+            .await
+            .unwrap()["agent_id"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        let (in_tx, in_rx) = mpsc::unbounded_channel();
+        let (out_tx, mut out_rx) = mpsc::unbounded_channel();
+        let serving = tokio::spawn(async move { server.serve(in_rx, out_tx).await });
+        let cancel = |id: u64| {
+            let msg = json!({"jsonrpc":"2.0","method":"notifications/cancelled","params":{"requestId": id,"reason":"test"}});
+            serde_json::to_vec(&msg).unwrap()
+        };
+        in_tx
+            .send(request(
+                1,
+                "tools/call",
+                json!({"name":"wait","arguments":{"ms": 60_000}}),
+            ))
+            .unwrap();
+        in_tx
+            .send(request(
+                2,
+                "tools/call",
+                json!({"name":"get_agent_progress","arguments":{"agent_id": agent_id}}),
+            ))
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        in_tx.send(cancel(1)).unwrap();
+        in_tx.send(cancel(2)).unwrap();
+        // Cancelling an unknown request is ignored
+        in_tx.send(cancel(42)).unwrap();
+        in_tx
+            .send(request(
+                3,
+                "tools/call",
+                json!({"name":"metrics","arguments":{}}),
+            ))
+            .unwrap();
+        let resp = next_response(&mut out_rx).await;
+        assert_eq!(resp["id"], 3);
+        assert_eq!(resp["result"]["structuredContent"]["cancelled_count"], 2);
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !cancelled.load(Ordering::SeqCst) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("summarizer saw the cancellation");
+        drop(in_tx);
+        serving.await.unwrap();
+        assert_no_response(&mut out_rx);
+        manager.stop_all().await;
+    }
+
+    #[tokio::test]
+    async fn cancels_reach_requests_still_waiting_for_a_slot() {
+        let manager = Arc::new(crate::agents::manager::AgentManagerImpl::new(None, 1024));
+        let server = McpServer::new(manager, Arc::new(DummySummarizer)).with_max_concurrency(1);
+        initialize(&server).await;
+        let (in_tx, in_rx) = mpsc::unbounded_channel();
+        let (out_tx, mut out_rx) = mpsc::unbounded_channel();
+        let serving = tokio::spawn(async move { server.serve(in_rx, out_tx).await });
+        let cancel = |id: u64| {
+            let msg = json!({"jsonrpc":"2.0","method":"notifications/cancelled","params":{"requestId": id}});
+            serde_json::to_vec(&msg).unwrap()
+        };
+        let wait = |id: u64| {
+            request(
+                id,
+                "tools/call",
+                json!({"name":"wait","arguments":{"ms": 60_000}}),
+            )
+        };
+        // 1 takes the only slot and 2 queues behind it; the loop still reads the cancel for 2
+        in_tx.send(wait(1)).unwrap();
+        in_tx.send(wait(2)).unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        in_tx.send(cancel(2)).unwrap();
+        // Cancelled before its task ever ran
+        in_tx.send(wait(3)).unwrap();
+        in_tx.send(cancel(3)).unwrap();
+        in_tx.send(cancel(1)).unwrap();
+        in_tx
+            .send(request(
+                4,
+                "tools/call",
+                json!({"name":"metrics","arguments":{}}),
+            ))
+            .unwrap();
+        let resp = next_response(&mut out_rx).await;
+        assert_eq!(resp["id"], 4);
+        assert_eq!(resp["result"]["structuredContent"]["cancelled_count"], 3);
+        drop(in_tx);
+        serving.await.unwrap();
+        assert_no_response(&mut out_rx);
+    }
+
+    #[tokio::test]
+    async fn log_events_are_forwarded_at_the_session_level() {
+        use tracing_subscriber::layer::SubscriberExt;
+        let _guard = tracing::subscriber::set_default(
+            tracing_subscriber::registry().with(logging::McpLogLayer),
+        );
+        let manager = Arc::new(crate::agents::manager::AgentManagerImpl::new(None, 1024));
+        let server = McpServer::new(manager, Arc::new(DummySummarizer));
+        let resp = server
+            .handle_message(json!({"jsonrpc":"2.0","id":0,"method":"initialize","params":{}}))
+            .await
+            .unwrap();
+        assert!(resp["result"]["capabilities"]["logging"].is_object());
+        server
+            .handle_message(json!({"jsonrpc":"2.0","method":"notifications/initialized"}))
+            .await;
+        let (out_tx, mut out_rx) = mpsc::unbounded_channel();
+        let notifier = server.spawn_notifier(out_tx);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let mut next_forwarded = async || loop {
+            let msg = tokio::time::timeout(Duration::from_secs(5), out_rx.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            let text = msg["params"]["data"]["message"].as_str().unwrap_or("");
+            if text.starts_with("forwarding test") {
+                return msg;
+            }
+        };
+
+        // Below the default level of warning
+        tracing::info!("forwarding test: info before setLevel");
+        tracing::warn!(agent_id = "a1", "forwarding test: warning");
+        let msg = next_forwarded().await;
+        assert_eq!(msg["method"], "notifications/message");
+        assert_eq!(msg["params"]["level"], "warning");
+        assert_eq!(msg["params"]["logger"], "agent/a1");
+
+        let set_level = |level: &str| json!({"jsonrpc":"2.0","id":1,"method":"logging/setLevel","params":{"level": level}});
+        let resp = server.handle_message(set_level("loud")).await.unwrap();
+        assert_eq!(resp["error"]["code"], -32602);
+        let resp = server.handle_message(set_level("debug")).await.unwrap();
+        assert_eq!(resp["result"], json!({}));
+        tracing::debug!("forwarding test: debug");
+        let msg = next_forwarded().await;
+        assert_eq!(msg["params"]["level"], "debug");
+        assert_eq!(msg["params"]["data"]["message"], "forwarding test: debug");
+        notifier.abort();
+    }
+
+    #[tokio::test]
+    async fn tool_results_carry_text_and_structured_content() {
+        for tool in list_tools_schema() {
+            assert_eq!(tool["outputSchema"]["type"], "object", "{}", tool["name"]);
+        }
+        let manager = Arc::new(crate::agents::manager::AgentManagerImpl::new(None, 1024));
+        let server = McpServer::new(manager, Arc::new(DummySummarizer));
+        initialize(&server).await;
+        let resp = server
+            .handle_message(json!({"jsonrpc":"2.0","id":1,"method":"tools/call","params":{"name":"wait","arguments":{"ms": 1}}}))
+            .await
+            .unwrap();
+        let result = &resp["result"];
+        assert_eq!(result["isError"], false);
+        assert_eq!(result["structuredContent"], json!({"waited_ms": 1}));
+        assert_eq!(result["content"][0]["type"], "text");
+        let text: Value =
+            serde_json::from_str(result["content"][0]["text"].as_str().unwrap()).unwrap();
+        assert_eq!(text, result["structuredContent"]);
+    }
+
+    #[tokio::test]
+    async fn tool_failures_are_error_results_with_codes() {
+        let manager = Arc::new(crate::agents::manager::AgentManagerImpl::new(None, 1024));
+        let server = McpServer::new(manager, Arc::new(DummySummarizer));
+        initialize(&server).await;
+        let call = |id: u64, name: &str, arguments: Value| json!({"jsonrpc":"2.0","id": id,"method":"tools/call","params":{"name": name,"arguments": arguments}});
+
+        // The error object is carried in the text block
+        let error_of = |resp: &Value| -> Value {
+            assert_eq!(resp["result"]["isError"], true);
+            let text = resp["result"]["content"][0]["text"].as_str().unwrap();
+            serde_json::from_str::<Value>(text).unwrap()["error"].clone()
+        };
+
+        let resp = server
+            .handle_message(call(1, "stop_agent", json!({"agent_id": "missing"})))
+            .await
+            .unwrap();
+        let error = error_of(&resp);
+        assert_eq!(error["code"], "not_found");
+        assert_eq!(error["retryable"], false);
+        assert!(error["hint"].as_str().unwrap().contains("list_agents"));
+
+        // A window size only makes sense for a terminal agent
+        let resp = server
+            .handle_message(call(
+                7,
+                "create_agent",
+                json!({"pty_size": {"rows": 30, "cols": 100}}),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(error_of(&resp)["code"], "invalid_arguments");
+
+        // Signals outside the schema's enum are rejected, not treated as a graceful stop
+        for signal in ["KILL", "sigkill", "hup"] {
+            let resp = server
+                .handle_message(call(
+                    6,
+                    "stop_agent",
+                    json!({"agent_id": "missing", "signal": signal}),
+                ))
+                .await
+                .unwrap();
+            assert_eq!(error_of(&resp)["code"], "invalid_arguments");
+        }
+
+        // Structured content, when present, must satisfy the tool's outputSchema; error results
+        // leave it out rather than send an object missing the schema's required fields
+        let list = server
+            .handle_message(json!({"jsonrpc":"2.0","id":9,"method":"tools/list"}))
+            .await
+            .unwrap();
+        let schema = list["result"]["tools"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|t| t["name"] == "stop_agent")
+            .unwrap()["outputSchema"]
+            .clone();
+        let structured = &resp["result"]["structuredContent"];
+        assert!(
+            structured.is_null()
+                || schema["required"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .all(|k| structured.get(k.as_str().unwrap()).is_some()),
+            "{structured} does not match {schema}"
+        );
+
+        let resp = server
+            .handle_message(call(2, "send_agent_input", json!({"agent_id": 7})))
+            .await
+            .unwrap();
+        assert_eq!(error_of(&resp)["code"], "invalid_arguments");
+        let resp = server
+            .handle_message(call(
+                4,
+                "wait_for_output",
+                json!({"agent_id": "a", "pattern": "(unclosed"}),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(error_of(&resp)["code"], "invalid_arguments");
+        for steps in [json!([]), json!([{"expect": []}])] {
+            let resp = server
+                .handle_message(call(
+                    5,
+                    "send_and_expect",
+                    json!({"agent_id": "a", "steps": steps}),
+                ))
+                .await
+                .unwrap();
+            assert_eq!(error_of(&resp)["code"], "invalid_arguments");
+        }
+
+        // Unknown tools are a protocol error
+        let resp = server
+            .handle_message(call(3, "no_such_tool", json!({})))
+            .await
+            .unwrap();
+        assert_eq!(resp["error"]["code"], -32602);
+    }
+
+    #[tokio::test]
+    async fn initialize_negotiates_version_and_gates_requests() {
+        let manager = Arc::new(crate::agents::manager::AgentManagerImpl::new(None, 1024));
+        let server = McpServer::new(manager, Arc::new(DummySummarizer));
+        let tools_list = json!({"jsonrpc":"2.0","id":1,"method":"tools/list"});
+        let resp = server.handle_message(tools_list.clone()).await.unwrap();
+        assert_eq!(resp["error"]["code"], -32600);
+        let ping = json!({"jsonrpc":"2.0","id":2,"method":"ping"});
+        assert!(server.handle_message(ping).await.unwrap()["result"].is_object());
+
+        let init = |version: &str| {
+            json!({"jsonrpc":"2.0","id":3,"method":"initialize","params":{
+                "protocolVersion": version,
+                "capabilities": {"roots": {"listChanged": true}},
+                "clientInfo": {"name": "test-client", "version": "1.2.3"}
+            }})
+        };
+        let resp = server.handle_message(init("2025-03-26")).await.unwrap();
+        let result = &resp["result"];
+        assert_eq!(result["protocolVersion"], "2025-03-26");
+        assert_eq!(result["capabilities"]["resources"]["listChanged"], true);
+        assert_eq!(
+            result["capabilities"]["tools"],
+            json!({"listChanged": false})
+        );
+        assert!(!server.session.is_ready());
+
+        // Requests are accepted once initialize is answered; a second initialize is not
+        let resp = server.handle_message(tools_list).await.unwrap();
+        assert!(resp["result"]["tools"].is_array());
+        let resp = server.handle_message(init("2025-03-26")).await.unwrap();
+        assert_eq!(resp["error"]["code"], -32600);
+
+        server
+            .handle_message(json!({"jsonrpc":"2.0","method":"notifications/initialized"}))
+            .await;
+        assert!(server.session.is_ready());
+        let info = server
+            .handle_message(json!({"jsonrpc":"2.0","id":4,"method":"server/info"}))
+            .await
+            .unwrap();
+        let client = &info["result"]["client"];
+        assert_eq!(client["name"], "test-client");
+        assert_eq!(client["version"], "1.2.3");
+        assert_eq!(client["capabilities"]["roots"]["listChanged"], true);
+
+        // An unknown version gets our newest instead of an echo
+        let other = server.new_session();
+        let resp = other.handle_message(init("1999-01-01")).await.unwrap();
+        assert_eq!(
+            resp["result"]["protocolVersion"],
+            SUPPORTED_PROTOCOL_VERSIONS[0]
+        );
+    }
+
+    #[tokio::test]
+    async fn subscribe_rejects_unknown_uri() {
+        let manager = Arc::new(crate::agents::manager::AgentManagerImpl::new(None, 1024));
+        let server = McpServer::new(manager, Arc::new(DummySummarizer));
+        initialize(&server).await;
+        let resp = server
+            .handle_message(json!({"jsonrpc":"2.0","id":1,"method":"resources/subscribe","params":{"uri":"file:///etc/passwd"}}))
+            .await
+            .unwrap();
+        assert_eq!(resp["error"]["code"], -32602);
+    }
+
     #[tokio::test]
     async fn dispatch_create_list_and_metrics() {
         let manager = Arc::new(crate::agents::manager::AgentManagerImpl::new(
@@ -493,7 +2565,7 @@ mod tests {
             }),
             16 * 1024,
         ));
-        let server = StdioMcpServer::new(manager.clone(), Arc::new(DummySummarizer));
+        let server = McpServer::new(manager.clone(), Arc::new(DummySummarizer));
         // create agent
         let args: Vec<String> = if cfg!(windows) {
             vec!["/C".into(), "more".into()]
@@ -507,6 +2579,7 @@ mod tests {
                     "name":"t",
                     "args": args
                 }),
+                &CancellationToken::new(),
             )
             .await
             .unwrap();
@@ -517,18 +2590,26 @@ mod tests {
             .to_string();
         // list
         let list = server
-            .dispatch_tool("list_agents", serde_json::json!({}))
+            .dispatch_tool(
+                "list_agents",
+                serde_json::json!({}),
+                &CancellationToken::new(),
+            )
             .await
             .unwrap();
         assert!(list.get("agents").is_some());
         // metrics
         let _ = server
-            .dispatch_tool("metrics", serde_json::json!({}))
+            .dispatch_tool("metrics", serde_json::json!({}), &CancellationToken::new())
             .await
             .unwrap();
         // stop
         let _ = server
-            .dispatch_tool("stop_agent", serde_json::json!({"agent_id": id}))
+            .dispatch_tool(
+                "stop_agent",
+                serde_json::json!({"agent_id": id}),
+                &CancellationToken::new(),
+            )
             .await
             .unwrap();
     }
@@ -548,14 +2629,18 @@ mod tests {
             }),
             16 * 1024,
         ));
-        let server = StdioMcpServer::new(manager.clone(), Arc::new(DummySummarizer));
+        let server = McpServer::new(manager.clone(), Arc::new(DummySummarizer));
         let args: Vec<String> = if cfg!(windows) {
             vec!["/C".into(), "more".into()]
         } else {
             Vec::new()
         };
         let resp = server
-            .dispatch_tool("create_agent", serde_json::json!({ "args": args }))
+            .dispatch_tool(
+                "create_agent",
+                serde_json::json!({ "args": args }),
+                &CancellationToken::new(),
+            )
             .await
             .unwrap();
         let id = resp
@@ -570,6 +2655,7 @@ mod tests {
             .dispatch_tool(
                 "get_agent_progress",
                 serde_json::json!({"agent_id": id, "max_tokens": 12}),
+                &CancellationToken::new(),
             )
             .await
             .unwrap();
@@ -589,12 +2675,96 @@ struct CreateAgentRequestWire {
     working_dir: Option<std::path::PathBuf>,
     env: Option<std::collections::HashMap<String, String>>,
     args: Option<Vec<String>>,
+    idle_reap_exempt: Option<bool>,
+    limits: Option<ResourceLimits>,
+    restart_policy: Option<RestartPolicy>,
+    transcript: Option<bool>,
+    pty: Option<bool>,
+    pty_size: Option<PtySize>,
+    keep_raw_output: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ReadAgentOutput {
+    agent_id: String,
+    cursor: Option<u64>,
+    limit: Option<usize>,
+    #[serde(flatten)]
+    filter: OutputFilter,
+}
+
+#[derive(Debug, Deserialize)]
+struct GetAgentTranscript {
+    agent_id: String,
+    from: Option<u64>,
+    limit: Option<usize>,
+    #[serde(flatten)]
+    filter: OutputFilter,
 }
 
 #[derive(Debug, Deserialize)]
 struct SendAgentInput {
     agent_id: String,
     input: String,
+    #[serde(default = "default_true")]
+    newline: bool,
+    #[serde(default)]
+    escapes: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Debug, Deserialize)]
+struct CloseAgentStdin {
+    agent_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ResizeAgentPty {
+    agent_id: String,
+    rows: u16,
+    cols: u16,
+}
+
+#[derive(Debug, Deserialize)]
+struct SignalAgent {
+    agent_id: String,
+    signal: AgentSignal,
+}
+
+// Decode backslash escapes so control characters and arbitrary bytes can be sent as text
+fn decode_escapes(input: &str) -> Result<Vec<u8>, String> {
+    let mut out = Vec::with_capacity(input.len());
+    let mut chars = input.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buf = [0u8; 4];
+            out.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+        let byte = match chars.next() {
+            Some('n') => b'\n',
+            Some('r') => b'\r',
+            Some('t') => b'\t',
+            Some('e') => 0x1b,
+            Some('0') => 0,
+            Some('\\') => b'\\',
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+                // from_str_radix alone would accept a sign, as in \x+f
+                Some(&hex)
+                    .filter(|h| h.len() == 2 && h.chars().all(|c| c.is_ascii_hexdigit()))
+                    .and_then(|h| u8::from_str_radix(h, 16).ok())
+                    .ok_or_else(|| format!("invalid escape \\x{hex}: expected two hex digits"))?
+            }
+            Some(other) => return Err(format!("unknown escape \\{other}")),
+            None => return Err("input ends with a lone backslash".into()),
+        };
+        out.push(byte);
+    }
+    Ok(out)
 }
 
 #[derive(Debug, Deserialize)]
@@ -602,6 +2772,8 @@ struct GetAgentProgress {
     agent_id: String,
     instructions: Option<String>,
     max_tokens: Option<usize>,
+    #[serde(flatten)]
+    filter: OutputFilter,
 }
 
 #[derive(Debug, Deserialize)]
@@ -613,7 +2785,77 @@ struct ResetAgent {
 #[derive(Debug, Deserialize)]
 struct StopAgent {
     agent_id: String,
-    signal: Option<String>,
+    signal: Option<StopSignal>,
+    grace_ms: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct WaitForOutput {
+    agent_id: String,
+    #[serde(default, deserialize_with = "deserialize_pattern")]
+    pattern: Option<regex::Regex>,
+    idle_ms: Option<u64>,
+    #[serde(default)]
+    until_exit: bool,
+    timeout_ms: Option<u64>,
+    cursor: Option<u64>,
+    #[serde(flatten)]
+    filter: OutputFilter,
+}
+
+#[derive(Debug, Deserialize)]
+struct SendAndExpect {
+    agent_id: String,
+    #[serde(deserialize_with = "deserialize_steps")]
+    steps: Vec<ExpectStepParams>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ExpectStepParams {
+    send: Option<String>,
+    #[serde(deserialize_with = "deserialize_patterns")]
+    expect: Vec<regex::Regex>,
+    timeout_ms: Option<u64>,
+}
+
+// Compile patterns while parsing so a bad one is reported as invalid arguments
+fn deserialize_pattern<'de, D>(de: D) -> Result<Option<regex::Regex>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let Some(pattern) = Option::<String>::deserialize(de)? else {
+        return Ok(None);
+    };
+    regex::Regex::new(&pattern)
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+fn deserialize_patterns<'de, D>(de: D) -> Result<Vec<regex::Regex>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let patterns = Vec::<String>::deserialize(de)?;
+    if patterns.is_empty() {
+        return Err(serde::de::Error::custom(
+            "expect needs at least one pattern",
+        ));
+    }
+    patterns
+        .iter()
+        .map(|p| regex::Regex::new(p).map_err(serde::de::Error::custom))
+        .collect()
+}
+
+fn deserialize_steps<'de, D>(de: D) -> Result<Vec<ExpectStepParams>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let steps = Vec::<ExpectStepParams>::deserialize(de)?;
+    if steps.is_empty() {
+        return Err(serde::de::Error::custom("steps needs at least one step"));
+    }
+    Ok(steps)
 }
 
 #[derive(Debug, Deserialize)]
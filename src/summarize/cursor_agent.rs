@@ -1,6 +1,7 @@
 use crate::errors::SummarizeError;
-use crate::summarize::{SummarizeResult, Summarizer};
-use std::process::{Command, Stdio};
+use crate::summarize::{run_command, SummarizeResult, Summarizer};
+use std::process::Command;
+use tokio_util::sync::CancellationToken;
 
 pub struct CursorAgentSummarizer {
     model: String,
@@ -22,6 +23,16 @@ impl Summarizer for CursorAgentSummarizer {
         context: &str,
         instructions: Option<&str>,
         max_tokens: usize,
+    ) -> Result<SummarizeResult, SummarizeError> {
+        self.summarize_cancellable(context, instructions, max_tokens, &CancellationToken::new())
+    }
+
+    fn summarize_cancellable(
+        &self,
+        context: &str,
+        instructions: Option<&str>,
+        max_tokens: usize,
+        cancel: &CancellationToken,
     ) -> Result<SummarizeResult, SummarizeError> {
         let max_tokens = max_tokens.min(1000);
         let prompt = format!(
@@ -38,31 +49,12 @@ impl Summarizer for CursorAgentSummarizer {
         };
 
         // Hypothetical CLI flags: --model, --max-tokens, read prompt from stdin
-        let mut child = Command::new(&self.bin)
-            .arg("--model")
+        let mut cmd = Command::new(&self.bin);
+        cmd.arg("--model")
             .arg(model)
             .arg("--max-tokens")
-            .arg(max_tokens.to_string())
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .spawn()
-            .map_err(|_| SummarizeError::Unavailable)?;
-
-        if let Some(mut stdin) = child.stdin.take() {
-            use std::io::Write;
-            stdin
-                .write_all(prompt.as_bytes())
-                .map_err(|e| SummarizeError::Other(format!("write prompt: {e}")))?;
-        }
-
-        let mut out = String::new();
-        if let Some(mut s) = child.stdout.take() {
-            use std::io::Read as _;
-            s.read_to_string(&mut out)
-                .map_err(|e| SummarizeError::Other(format!("read stdout: {e}")))?;
-        }
-        let _ = child.wait();
+            .arg(max_tokens.to_string());
+        let mut out = run_command(cmd, Some(prompt), cancel)?;
 
         if out.trim().is_empty() {
             return Err(SummarizeError::Unavailable);
@@ -116,4 +108,41 @@ mod tests {
         assert!(!res.summary.is_empty());
         assert!(res.summary.len() <= 40); // ~4 tokens => ~16 chars, allow slack
     }
+
+    #[test]
+    fn cancellation_kills_the_subprocess() {
+        let tmp = tempfile::tempdir().unwrap();
+        let pid_file = tmp.path().join("pid");
+        // The background sleep inherits stdout, so the reader only finishes once it dies too
+        let script = make_echo_script(&format!(
+            "#!/bin/sh\necho $$ > {}\nsleep 30 &\nexec sleep 30",
+            pid_file.display()
+        ));
+        let s = CursorAgentSummarizer {
+            model: "auto".into(),
+            bin: script.display().to_string(),
+        };
+        let cancel = CancellationToken::new();
+        let trigger = cancel.clone();
+        let canceller = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(200));
+            trigger.cancel();
+        });
+        let started = std::time::Instant::now();
+        let err = s
+            .summarize_cancellable("ctx", None, 16, &cancel)
+            .unwrap_err();
+        canceller.join().unwrap();
+        assert!(matches!(err, SummarizeError::Cancelled));
+        assert!(started.elapsed() < std::time::Duration::from_secs(10));
+        let pid: i32 = std::fs::read_to_string(&pid_file)
+            .unwrap()
+            .trim()
+            .parse()
+            .unwrap();
+        // SAFETY: kill(2) with signal 0 only checks for existence
+        assert_ne!(unsafe { libc::kill(pid, 0) }, 0, "subprocess still running");
+        #[cfg(target_os = "linux")]
+        assert!(crate::agents::process::group_members(pid as u32).is_empty());
+    }
 }
@@ -1,7 +1,7 @@
 use crate::errors::SummarizeError;
-use crate::summarize::{SummarizeResult, Summarizer};
-use std::io::Read;
-use std::process::{Command, Stdio};
+use crate::summarize::{run_command, SummarizeResult, Summarizer};
+use std::process::Command;
+use tokio_util::sync::CancellationToken;
 
 pub struct LlamaCppSummarizer {
     model_path: String,
@@ -25,6 +25,16 @@ impl Summarizer for LlamaCppSummarizer {
         context: &str,
         instructions: Option<&str>,
         max_tokens: usize,
+    ) -> Result<SummarizeResult, SummarizeError> {
+        self.summarize_cancellable(context, instructions, max_tokens, &CancellationToken::new())
+    }
+
+    fn summarize_cancellable(
+        &self,
+        context: &str,
+        instructions: Option<&str>,
+        max_tokens: usize,
+        cancel: &CancellationToken,
     ) -> Result<SummarizeResult, SummarizeError> {
         let max_tokens = max_tokens.min(1000);
         let prompt = format!(
@@ -34,26 +44,14 @@ impl Summarizer for LlamaCppSummarizer {
         );
 
         // Invoke llama.cpp CLI: llama-cli -m <model.gguf> -p <prompt> -n <max_tokens>
-        let mut child = Command::new(&self.cli_path)
-            .arg("-m")
+        let mut cmd = Command::new(&self.cli_path);
+        cmd.arg("-m")
             .arg(&self.model_path)
             .arg("-p")
             .arg(&prompt)
             .arg("-n")
-            .arg(max_tokens.to_string())
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .spawn()
-            .map_err(|_| SummarizeError::Unavailable)?;
-
-        let mut out = String::new();
-        if let Some(mut s) = child.stdout.take() {
-            use std::io::Read as _;
-            s.read_to_string(&mut out)
-                .map_err(|e| SummarizeError::Other(format!("read stdout: {e}")))?;
-        }
-        // Best-effort: wait but don't block forever
-        let _ = child.wait();
+            .arg(max_tokens.to_string());
+        let mut out = run_command(cmd, None, cancel)?;
 
         if out.trim().is_empty() {
             return Err(SummarizeError::Unavailable);
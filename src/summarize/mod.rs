@@ -1,4 +1,8 @@
 use crate::errors::SummarizeError;
+use std::io::{Read, Write};
+use std::process::{Command, Stdio};
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
 
 #[derive(Debug, Clone)]
 pub struct SummarizeResult {
@@ -14,6 +18,90 @@ pub trait Summarizer: Send + Sync {
         instructions: Option<&str>,
         max_tokens: usize,
     ) -> Result<SummarizeResult, SummarizeError>;
+
+    /// Like `summarize`, but gives up with `SummarizeError::Cancelled` once `cancel` fires.
+    /// Backends that run a subprocess kill it; by default cancellation is only checked up front.
+    fn summarize_cancellable(
+        &self,
+        context: &str,
+        instructions: Option<&str>,
+        max_tokens: usize,
+        cancel: &CancellationToken,
+    ) -> Result<SummarizeResult, SummarizeError> {
+        if cancel.is_cancelled() {
+            return Err(SummarizeError::Cancelled);
+        }
+        self.summarize(context, instructions, max_tokens)
+    }
+}
+
+// How often a running summarizer subprocess is checked for exit or cancellation
+const CANCEL_POLL: Duration = Duration::from_millis(20);
+
+/// Run `cmd`, feeding it `input` on stdin, and return its stdout once it exits. The child, and on
+/// unix anything it started, is killed as soon as `cancel` fires.
+fn run_command(
+    mut cmd: Command,
+    input: Option<String>,
+    cancel: &CancellationToken,
+) -> Result<String, SummarizeError> {
+    if cancel.is_cancelled() {
+        return Err(SummarizeError::Cancelled);
+    }
+    let stdin = if input.is_some() {
+        Stdio::piped()
+    } else {
+        Stdio::null()
+    };
+    // Lead a new process group, so cancelling also kills helpers the backend left holding stdout
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut cmd, 0);
+    // Nothing reads stderr, and a pipe left undrained would stall a chatty backend once full
+    let mut child = cmd
+        .stdin(stdin)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|_| SummarizeError::Unavailable)?;
+    // Feed stdin and drain stdout on helper threads so this one can watch for cancellation
+    let writer =
+        child.stdin.take().zip(input).map(|(mut stdin, input)| {
+            std::thread::spawn(move || stdin.write_all(input.as_bytes()))
+        });
+    let mut stdout = child.stdout.take().expect("stdout is piped");
+    let reader = std::thread::spawn(move || {
+        let mut out = String::new();
+        stdout.read_to_string(&mut out).map(|_| out)
+    });
+    loop {
+        if cancel.is_cancelled() {
+            #[cfg(unix)]
+            let _ = crate::agents::process::signal_group(child.id(), libc::SIGKILL);
+            let _ = child.kill();
+            let _ = child.wait();
+            // With the group gone the pipes are closed, so both helpers finish promptly
+            if let Some(writer) = writer {
+                let _ = writer.join();
+            }
+            let _ = reader.join();
+            return Err(SummarizeError::Cancelled);
+        }
+        match child.try_wait() {
+            Ok(Some(_)) => break,
+            Ok(None) => std::thread::sleep(CANCEL_POLL),
+            Err(e) => return Err(SummarizeError::Other(format!("wait: {e}"))),
+        }
+    }
+    if let Some(writer) = writer {
+        writer
+            .join()
+            .map_err(|_| SummarizeError::Other("stdin writer panicked".into()))?
+            .map_err(|e| SummarizeError::Other(format!("write prompt: {e}")))?;
+    }
+    reader
+        .join()
+        .map_err(|_| SummarizeError::Other("stdout reader panicked".into()))?
+        .map_err(|e| SummarizeError::Other(format!("read stdout: {e}")))
 }
 
 mod cursor_agent;
@@ -1,6 +1,7 @@
 use crate::errors::SummarizeError;
 use crate::summarize::{SummarizeResult, Summarizer};
 use reqwest::blocking::Client;
+use tokio_util::sync::CancellationToken;
 
 pub struct OllamaSummarizer {
     host: String,
@@ -24,6 +25,17 @@ impl Summarizer for OllamaSummarizer {
         context: &str,
         instructions: Option<&str>,
         max_tokens: usize,
+    ) -> Result<SummarizeResult, SummarizeError> {
+        self.summarize_cancellable(context, instructions, max_tokens, &CancellationToken::new())
+    }
+
+    // An in-flight HTTP request cannot be interrupted; cancellation is checked between attempts
+    fn summarize_cancellable(
+        &self,
+        context: &str,
+        instructions: Option<&str>,
+        max_tokens: usize,
+        cancel: &CancellationToken,
     ) -> Result<SummarizeResult, SummarizeError> {
         let max_tokens = max_tokens.min(1000);
         let prompt = format!(
@@ -44,6 +56,9 @@ impl Summarizer for OllamaSummarizer {
         // Simple retry with backoff
         let mut last_err: Option<String> = None;
         for attempt in 0..3 {
+            if cancel.is_cancelled() {
+                return Err(SummarizeError::Cancelled);
+            }
             match self.client.post(&url).json(&body).send() {
                 Ok(resp) => {
                     if resp.status().is_success() {
@@ -0,0 +1,213 @@
+use assert_cmd::prelude::*;
+use reqwest::blocking::{Client, Response};
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader};
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+const SESSION: &str = "mcp-session-id";
+
+// Kill the server even when an assertion fails
+struct Server(Child);
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+fn free_port() -> u16 {
+    std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+fn post(client: &Client, url: &str, session: Option<&str>, msg: Value) -> Response {
+    let mut req = client
+        .post(url)
+        .header("Accept", "application/json, text/event-stream")
+        .json(&msg);
+    if let Some(s) = session {
+        req = req.header(SESSION, s);
+    }
+    req.send().unwrap()
+}
+
+// Next `data:` payload from an SSE stream
+fn next_event<R: BufRead>(stream: &mut R) -> Value {
+    let mut line = String::new();
+    loop {
+        line.clear();
+        let n = stream.read_line(&mut line).unwrap();
+        assert!(n > 0, "event stream closed");
+        if let Some(data) = line.trim_end().strip_prefix("data:") {
+            return serde_json::from_str(data.trim()).unwrap();
+        }
+    }
+}
+
+#[test]
+fn http_end_to_end() {
+    let (bin, args): (String, Vec<String>) = if cfg!(windows) {
+        ("cmd.exe".into(), vec!["/C".into(), "more".into()])
+    } else {
+        ("/bin/cat".into(), vec![])
+    };
+    let port = free_port();
+    let child = Command::cargo_bin("cursor-mcp-subagents")
+        .unwrap()
+        .args([
+            "--transport",
+            "http",
+            "--listen",
+            &format!("127.0.0.1:{port}"),
+        ])
+        .env("CURSOR_AGENT_PATH", bin)
+        .env("SUMMARY_BACKEND", "extractive")
+        .env("NOTIFY_DEBOUNCE_MS", "20")
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let _server = Server(child);
+    let url = format!("http://127.0.0.1:{port}/mcp");
+    let client = Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()
+        .unwrap();
+
+    // Wait for the listener
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let resp = loop {
+        let attempt = client
+            .post(&url)
+            .json(&json!({"jsonrpc":"2.0","id":1,"method":"initialize","params":{}}))
+            .send();
+        match attempt {
+            Ok(resp) => break resp,
+            Err(_) if Instant::now() < deadline => std::thread::sleep(Duration::from_millis(50)),
+            Err(e) => panic!("server did not start: {e}"),
+        }
+    };
+    assert!(resp.status().is_success());
+    let session = resp.headers()[SESSION].to_str().unwrap().to_string();
+    let body: Value = resp.json().unwrap();
+    assert_eq!(body["id"], 1);
+    assert!(body["result"]["serverInfo"].is_object());
+
+    // Requests outside a session are refused
+    let resp = post(
+        &client,
+        &url,
+        None,
+        json!({"jsonrpc":"2.0","id":2,"method":"tools/list"}),
+    );
+    assert_eq!(resp.status(), 400);
+
+    let resp = post(
+        &client,
+        &url,
+        Some(&session),
+        json!({"jsonrpc":"2.0","method":"notifications/initialized"}),
+    );
+    assert_eq!(resp.status(), 202);
+
+    // Batches are answered with an array in request order
+    let resp = post(
+        &client,
+        &url,
+        Some(&session),
+        json!([
+            {"jsonrpc":"2.0","id":"a","method":"ping"},
+            {"jsonrpc":"2.0","method":"notifications/initialized"},
+            {"jsonrpc":"2.0","id":"b","method":"tools/list"},
+        ]),
+    );
+    let body: Value = resp.json().unwrap();
+    let ids: Vec<_> = body.as_array().unwrap().iter().map(|r| &r["id"]).collect();
+    assert_eq!(ids, [&json!("a"), &json!("b")]);
+    let resp = post(&client, &url, Some(&session), json!({"id": 9}));
+    assert_eq!(resp.status(), 400);
+    assert_eq!(resp.json::<Value>().unwrap()["error"]["code"], -32600);
+
+    // Notifications arrive on the GET stream
+    let stream = client
+        .get(&url)
+        .header("Accept", "text/event-stream")
+        .header(SESSION, &session)
+        .send()
+        .unwrap();
+    assert!(stream.status().is_success());
+    let mut events = BufReader::new(stream);
+    let resp = post(
+        &client,
+        &url,
+        Some(&session),
+        json!({"jsonrpc":"2.0","id":3,"method":"resources/subscribe","params":{"uri":"mcp://cursor-mcp-subagents/agents"}}),
+    );
+    assert!(resp.json::<Value>().unwrap()["result"].is_object());
+
+    let resp = post(
+        &client,
+        &url,
+        Some(&session),
+        json!({"jsonrpc":"2.0","id":4,"method":"tools/call","params":{"name":"create_agent","arguments":{"args": args}}}),
+    );
+    let body: Value = resp.json().unwrap();
+    let agent_id = body["result"]["structuredContent"]["agent_id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    let event = next_event(&mut events);
+    assert!(event["method"]
+        .as_str()
+        .unwrap()
+        .starts_with("notifications/resources/"));
+
+    // A second client shares the same agents
+    let resp = client
+        .post(&url)
+        .json(&json!({"jsonrpc":"2.0","id":1,"method":"initialize","params":{}}))
+        .send()
+        .unwrap();
+    let other = resp.headers()[SESSION].to_str().unwrap().to_string();
+    assert_ne!(other, session);
+    let resp = post(
+        &client,
+        &url,
+        Some(&other),
+        json!({"jsonrpc":"2.0","id":2,"method":"tools/call","params":{"name":"list_agents","arguments":{}}}),
+    );
+    let body: Value = resp.json().unwrap();
+    let agents = body["result"]["structuredContent"]["agents"]
+        .as_array()
+        .unwrap();
+    assert!(agents.iter().any(|a| a["agent_id"] == agent_id.as_str()));
+
+    // Ending a session leaves the agents running
+    let resp = client
+        .delete(&url)
+        .header(SESSION, &session)
+        .send()
+        .unwrap();
+    assert_eq!(resp.status(), 204);
+    let resp = post(
+        &client,
+        &url,
+        Some(&session),
+        json!({"jsonrpc":"2.0","id":5,"method":"tools/list"}),
+    );
+    assert_eq!(resp.status(), 404);
+    let resp = post(
+        &client,
+        &url,
+        Some(&other),
+        json!({"jsonrpc":"2.0","id":3,"method":"tools/call","params":{"name":"stop_agent","arguments":{"agent_id": agent_id}}}),
+    );
+    let body: Value = resp.json().unwrap();
+    assert_eq!(body["result"]["structuredContent"]["stopped"], true);
+}
@@ -83,9 +83,7 @@ fn mcp_end_to_end() {
     let resp = read_framed(&mut stdout);
     let agent_id = resp
         .get("result")
-        .and_then(|r| r.get("content"))
-        .and_then(|c| c.get(0))
-        .and_then(|j| j.get("json"))
+        .and_then(|r| r.get("structuredContent"))
         .and_then(|j| j.get("agent_id"))
         .and_then(|x| x.as_str())
         .unwrap()
@@ -113,12 +111,14 @@ fn mcp_end_to_end() {
         }),
     );
     let resp = read_framed(&mut stdout);
-    let content = resp
-        .get("result")
-        .and_then(|r| r.get("content"))
-        .and_then(|c| c.get(0))
-        .and_then(|j| j.get("json"))
-        .unwrap();
+    let result = resp.get("result").unwrap();
+    let content = result.get("structuredContent").unwrap();
+    // The text block carries the same result serialized
+    let text = result["content"][0]["text"].as_str().unwrap();
+    assert_eq!(
+        &serde_json::from_str::<serde_json::Value>(text).unwrap(),
+        content
+    );
     assert_eq!(
         content.get("backend").and_then(|x| x.as_str()).unwrap(),
         "textrank"